@@ -0,0 +1,337 @@
+//! Distances and paths over the map, for the strategies and evaluation alike.
+//!
+//! Fields over a `State` see the map as it stands. Mountains and unknown obstacles are
+//! walls either way, while cities and generals someone else holds either block the way
+//! (`bfs`) or cost the armies needed to break through them (`weighted`). `Terrain` keeps
+//! those costs over a game and caches the costed fields over them.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use state::{State, TileType, DX, DY};
+
+/// Distance of the tiles no source can reach.
+pub const UNREACHABLE: u32 = u32::MAX;
+
+/// The neighbours of `(x, y)` on a `width` by `height` map, each with the direction of
+/// the move leading there.
+pub fn neighbours(width: usize,
+                  height: usize,
+                  x: usize,
+                  y: usize)
+                  -> impl Iterator<Item = (usize, usize, usize)> {
+    (0..4).filter_map(move |k| {
+        let nx = x as isize + DX[k];
+        let ny = y as isize + DY[k];
+        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            None
+        } else {
+            Some((nx as usize, ny as usize, k))
+        }
+    })
+}
+
+/// Steps between `a` and `b` on an open map.
+#[inline]
+pub fn manhattan(a: (usize, usize), b: (usize, usize)) -> usize {
+    a.0.max(b.0) - a.0.min(b.0) + a.1.max(b.1) - a.1.min(b.1)
+}
+
+/// The cost of a path from every tile to the nearest of its sources.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistanceMap {
+    width: usize,
+    height: usize,
+    distance: Vec<u32>,
+}
+
+impl DistanceMap {
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.distance[y * self.width + x]
+    }
+
+    #[inline]
+    pub fn is_reachable(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) != UNREACHABLE
+    }
+
+    /// The direction of the neighbour of `(x, y)` closest to a source, if it is any
+    /// closer than `(x, y)` itself.
+    pub fn step(&self, x: usize, y: usize) -> Option<usize> {
+        let here = self.get(x, y);
+        neighbours(self.width, self.height, x, y)
+            .filter(|&(nx, ny, _)| self.get(nx, ny) < here)
+            .min_by_key(|&(nx, ny, _)| self.get(nx, ny))
+            .map(|(_, _, k)| k)
+    }
+
+    /// Every tile from `(x, y)` down to the source it is closest to, both included, or
+    /// nothing if no source can be reached.
+    pub fn path_from(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        if !self.is_reachable(x, y) {
+            return vec![];
+        }
+        let mut path = vec![(x, y)];
+        let (mut x, mut y) = (x, y);
+        while let Some(k) = self.step(x, y) {
+            x = (x as isize + DX[k]) as usize;
+            y = (y as isize + DY[k]) as usize;
+            path.push((x, y));
+        }
+        path
+    }
+}
+
+/// Whether no army can ever stand on the tile, as far as we know.
+#[inline]
+pub fn is_wall(kind: &TileType) -> bool {
+    *kind == TileType::Mountain || *kind == TileType::Obstacle
+}
+
+/// Dijkstra from `sources` outwards, where `cost(x, y)` is what stepping onto a tile that
+/// is not a source costs, or `None` where the way is blocked.
+fn search<F>(width: usize, height: usize, sources: &[(usize, usize)], cost: F) -> DistanceMap
+    where F: Fn(usize, usize) -> Option<u32>
+{
+    let mut distance = vec![UNREACHABLE; width * height];
+    let mut queue = BinaryHeap::new();
+    for &(x, y) in sources {
+        distance[y * width + x] = 0;
+        queue.push(Reverse((0, x, y)));
+    }
+
+    while let Some(Reverse((here, x, y))) = queue.pop() {
+        if here > distance[y * width + x] {
+            continue;
+        }
+        // Armies move the other way, from the neighbour onto this tile.
+        let step = if here == 0 {
+            Some(1)
+        } else {
+            cost(x, y)
+        };
+        let step = match step {
+            Some(step) => step,
+            None => continue,
+        };
+        for (nx, ny, _) in neighbours(width, height, x, y) {
+            let next = here + step;
+            if next < distance[ny * width + nx] && cost(nx, ny).is_some() {
+                distance[ny * width + nx] = next;
+                queue.push(Reverse((next, nx, ny)));
+            }
+        }
+    }
+
+    DistanceMap {
+        width,
+        height,
+        distance,
+    }
+}
+
+/// Steps from every tile to the nearest of `sources` for `player`. Walls and the cities
+/// and generals of others block the way, except as sources.
+pub fn bfs(state: &State, player: usize, sources: &[(usize, usize)]) -> DistanceMap {
+    search(state.width(), state.height(), sources, |x, y| {
+        let tile = state.tile(x, y);
+        match tile.kind {
+            ref kind if is_wall(kind) => None,
+            TileType::City | TileType::General if tile.owner != player => None,
+            _ => Some(1),
+        }
+    })
+}
+
+/// What stepping onto `(x, y)` costs `player`: nothing gets through walls, and the cities
+/// and generals of others take a step and one more for every army defending them.
+fn step_cost(state: &State, player: usize, x: usize, y: usize) -> Option<u32> {
+    let tile = state.tile(x, y);
+    match tile.kind {
+        ref kind if is_wall(kind) => None,
+        TileType::City | TileType::General if tile.owner != player => Some(1 + tile.count),
+        _ => Some(1),
+    }
+}
+
+/// Like `bfs`, but cities and generals of others can be broken through for a step and
+/// one more for every army defending them.
+pub fn weighted(state: &State, player: usize, sources: &[(usize, usize)]) -> DistanceMap {
+    search(state.width(), state.height(), sources, |x, y| step_cost(state, player, x, y))
+}
+
+/// The cheapest way for `player` from `from` to `to`, both included, counting cities and
+/// generals in the way as `weighted` does.
+pub fn shortest_path(state: &State,
+                     player: usize,
+                     from: (usize, usize),
+                     to: (usize, usize))
+                     -> Option<Vec<(usize, usize)>> {
+    let path = weighted(state, player, &[to]).path_from(from.0, from.1);
+    if path.is_empty() { None } else { Some(path) }
+}
+
+/// What every tile costs us, player 0, to step onto as `weighted` counts it, and the
+/// fields over those costs. A game keeps one and updates it with every state, so the
+/// fields only need working out again once a wall comes down or a garrison changes.
+#[derive(Clone, Debug, Default)]
+pub struct Terrain {
+    width: usize,
+    height: usize,
+    costs: Vec<Option<u32>>,
+    fields: HashMap<usize, Arc<DistanceMap>>,
+}
+
+impl Terrain {
+    pub fn new(state: &State) -> Self {
+        let mut terrain = Terrain {
+            width: state.width(),
+            height: state.height(),
+            costs: vec![],
+            fields: HashMap::new(),
+        };
+        terrain.update(state);
+        terrain
+    }
+
+    /// Takes in the costs `state` shows. Returns whether any changed, which drops the
+    /// cached fields. A state of another size starts over.
+    pub fn update(&mut self, state: &State) -> bool {
+        if state.width() != self.width || state.height() != self.height {
+            *self = Terrain::new(state);
+            return true;
+        }
+
+        let mut costs = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                costs.push(step_cost(state, 0, x, y));
+            }
+        }
+        if costs == self.costs {
+            return false;
+        }
+        self.costs = costs;
+        self.fields.clear();
+        true
+    }
+
+    #[inline]
+    pub fn is_wall(&self, x: usize, y: usize) -> bool {
+        self.costs[y * self.width + x].is_none()
+    }
+
+    /// The field `weighted` gives from `(x, y)` alone, computed once until the costs
+    /// change.
+    pub fn distances_from(&mut self, x: usize, y: usize) -> &DistanceMap {
+        let (width, height) = (self.width, self.height);
+        let costs = &self.costs;
+        self.fields.entry(y * width + x).or_insert_with(|| {
+            Arc::new(search(width, height, &[(x, y)], |x, y| costs[y * width + x]))
+        })
+    }
+
+    /// The field from `(x, y)` if `distances_from` worked it out since the costs last
+    /// changed, to share with the states of a search.
+    pub fn cached_distances(&self, x: usize, y: usize) -> Option<Arc<DistanceMap>> {
+        self.fields.get(&(y * self.width + x)).cloned()
+    }
+
+    /// The number of distance fields currently cached.
+    pub fn cached(&self) -> usize {
+        self.fields.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use analysis::{self, Terrain, UNREACHABLE};
+    use game::Game;
+    use mapgen::Map;
+    use state::State;
+
+    /// 5x5 map with our general at (1, 1), a wall of mountains along x = 2 but for a city
+    /// of 10 at (2, 2), and the enemy general at (3, 3).
+    fn game() -> Game {
+        let mut map = Map::fixed(5, 5, 2);
+        for terrain in &mut map.terrain {
+            if *terrain == -2 {
+                *terrain = -1;
+            }
+        }
+        map.cities.clear();
+        map.armies[12] = 10;
+        map.cities.push(12);
+        for y in [0, 1, 3, 4].iter() {
+            map.terrain[y * 5 + 2] = -2;
+        }
+        map.to_game()
+    }
+
+    #[test]
+    fn test_bfs() {
+        let state = State::new(&game());
+        let field = analysis::bfs(&state, 0, &[(1, 1)]);
+        assert_eq!(field.get(1, 1), 0);
+        assert_eq!(field.get(0, 0), 2);
+        // Neither mountains nor the city let us through.
+        assert_eq!(field.get(2, 0), UNREACHABLE);
+        assert_eq!(field.get(2, 2), UNREACHABLE);
+        assert_eq!(field.get(3, 3), UNREACHABLE);
+
+        // With several sources, the nearest counts.
+        let field = analysis::bfs(&state, 0, &[(0, 0), (1, 4)]);
+        assert_eq!(field.get(1, 3), 1);
+        assert_eq!(field.get(0, 2), 2);
+        assert_eq!(field.path_from(0, 2).len(), 3);
+        assert!(field.step(0, 0).is_none());
+    }
+
+    #[test]
+    fn test_weighted() {
+        let state = State::new(&game());
+        let field = analysis::weighted(&state, 0, &[(3, 3)]);
+        // Breaking through the city costs a step and its ten armies.
+        assert_eq!(field.get(3, 2), 1);
+        assert_eq!(field.get(1, 2), 1 + 11 + 1);
+
+        let path = analysis::shortest_path(&state, 0, (1, 1), (3, 3)).unwrap();
+        assert_eq!(path.first(), Some(&(1, 1)));
+        assert_eq!(path.last(), Some(&(3, 3)));
+        assert!(path.contains(&(2, 2)));
+        assert_eq!(path.len(), 5);
+    }
+
+    #[test]
+    fn test_terrain() {
+        let mut game = game();
+        // The city and the top mountain are hidden.
+        game.raw_map[25 + 12] = -4;
+        game.raw_map[25 + 2] = -4;
+        let state = State::new(&game);
+        let mut terrain = Terrain::new(&state);
+        assert!(!terrain.is_wall(2, 2));
+        assert!(terrain.is_wall(2, 0));
+
+        // The way round through the city pays for its garrison, as `weighted` does.
+        let through_city = analysis::weighted(&state, 0, &[(0, 0)]).get(4, 0);
+        assert_eq!(terrain.distances_from(0, 0).get(4, 0), through_city);
+        assert_eq!(through_city, 8 + 10);
+        assert_eq!(terrain.cached(), 1);
+        assert!(terrain.cached_distances(0, 0).is_some());
+        assert!(terrain.cached_distances(4, 0).is_none());
+        assert!(!terrain.update(&state));
+
+        // Seeing past the obstacle opens a shorter way and drops the cache.
+        game.raw_map[25 + 2] = -1;
+        assert!(terrain.update(&State::new(&game)));
+        assert_eq!(terrain.cached(), 0);
+        assert_eq!(terrain.distances_from(0, 0).get(4, 0), 4);
+
+        // So does a garrison growing.
+        game.raw_map[12] = 11;
+        assert!(terrain.update(&State::new(&game)));
+    }
+}
@@ -0,0 +1,314 @@
+//! Offline games between bots, refereed by the full engine. Every bot sees the game only
+//! through the fog-filtered `game_update`s the server would send it, applied to a `Game`
+//! exactly as the live client does.
+//!
+//! The referee is a `State` built from a full view of the map with player index 0, so
+//! its players are numbered like the server's.
+
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use config::StrategyConfig;
+use events::{GameStart, GameUpdate, Score};
+use game::Game;
+use mapgen::Map;
+use state::{Action, State, TileType};
+use strategy::{self, Strategy};
+use time::precise_time_ns;
+use timing::TimeManager;
+
+const EMPTY: i32 = -1;
+const MOUNTAIN: i32 = -2;
+const FOG: i32 = -3;
+const FOG_OBSTACLE: i32 = -4;
+
+/// What one player sees of the referee state, in the server's encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    /// `[width, height, armies..., terrain...]`.
+    pub map: Vec<i32>,
+    pub cities: Vec<usize>,
+    pub generals: Vec<isize>,
+}
+
+/// Whether `player` owns the tile or one of its eight neighbours.
+fn is_visible(state: &State, player: usize, x: usize, y: usize) -> bool {
+    for ny in y.saturating_sub(1)..(y + 2).min(state.height()) {
+        for nx in x.saturating_sub(1)..(x + 2).min(state.width()) {
+            if state.tile(nx, ny).owner == player {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Renders `state` as `player` sees it. Hidden mountains and cities show up as obstacles,
+/// everything else hidden as plain fog.
+pub fn view(state: &State, player: usize) -> View {
+    let (width, height) = (state.width(), state.height());
+    let size = width * height;
+    let mut view = View {
+        map: vec![0; 2 + 2 * size],
+        cities: vec![],
+        generals: vec![-1; state.player_count()],
+    };
+    view.map[0] = width as i32;
+    view.map[1] = height as i32;
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = y * width + x;
+            let tile = state.tile(x, y);
+            if !is_visible(state, player, x, y) {
+                view.map[2 + size + idx] = match tile.kind {
+                    TileType::Mountain | TileType::City => FOG_OBSTACLE,
+                    _ => FOG,
+                };
+                continue;
+            }
+
+            view.map[2 + idx] = tile.count as i32;
+            view.map[2 + size + idx] = if tile.owner < state.player_count() {
+                tile.owner as i32
+            } else if tile.kind == TileType::Mountain {
+                MOUNTAIN
+            } else {
+                EMPTY
+            };
+            match tile.kind {
+                TileType::City => view.cities.push(idx),
+                TileType::General if tile.owner < state.player_count() => {
+                    view.generals[tile.owner] = idx as isize
+                }
+                _ => (),
+            }
+        }
+    }
+    view
+}
+
+/// Every player's score, largest army first as the server sends them.
+pub fn scores(state: &State) -> Vec<Score> {
+    let mut scores: Vec<Score> = (0..state.player_count())
+        .map(|player| {
+            Score {
+                index: player,
+                total: state.score(player),
+                tiles: state.land(player),
+                dead: !state.is_alive(player),
+            }
+        })
+        .collect();
+    scores.sort_by_key(|score| Reverse(score.total));
+    scores
+}
+
+/// Encodes `new` as a patch over `old` in the server's diff format: alternating counts
+/// of matching values and of replaced values, the latter followed by the values.
+pub fn diff<T>(old: &[T], new: &[T]) -> Vec<T>
+    where T: Copy + PartialEq + TryFrom<usize>,
+          <T as TryFrom<usize>>::Error: Debug
+{
+    let count = |n: usize| T::try_from(n).unwrap();
+    let mut patch = vec![];
+    let mut i = 0;
+    loop {
+        let start = i;
+        while i < new.len() && i < old.len() && old[i] == new[i] {
+            i += 1;
+        }
+        patch.push(count(i - start));
+        if i >= new.len() {
+            break;
+        }
+
+        let start = i;
+        while i < new.len() && (i >= old.len() || old[i] != new[i]) {
+            i += 1;
+        }
+        patch.push(count(i - start));
+        patch.extend_from_slice(&new[start..i]);
+        if i >= new.len() {
+            break;
+        }
+    }
+    patch
+}
+
+/// Turns the views one player is shown, turn after turn, into `game_update`s.
+#[derive(Debug, Default)]
+pub struct Observer {
+    last_map: Vec<i32>,
+    last_cities: Vec<usize>,
+}
+
+impl Observer {
+    pub fn new() -> Self {
+        Observer::default()
+    }
+
+    pub fn update(&mut self, state: &State, player: usize) -> GameUpdate {
+        let view = view(state, player);
+        let update = GameUpdate {
+            turn: state.turn() as i32,
+            map_diff: diff(&self.last_map, &view.map),
+            cities_diff: diff(&self.last_cities, &view.cities),
+            generals: view.generals,
+            scores: scores(state),
+        };
+        self.last_map = view.map;
+        self.last_cities = view.cities;
+        update
+    }
+}
+
+/// Whether the game has ended: one player or none left standing, or the turn limit hit.
+pub fn is_over(state: &State, max_turns: usize) -> bool {
+    let alive = (0..state.player_count()).filter(|&p| state.is_alive(p)).count();
+    alive <= 1 || state.turn() >= max_turns
+}
+
+/// The living player with the largest army.
+pub fn leader(state: &State) -> Option<usize> {
+    (0..state.player_count()).filter(|&p| state.is_alive(p)).max_by_key(|&p| state.score(p))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchResult {
+    pub winner: Option<usize>,
+    pub turns: usize,
+    /// Land and total army of every player when the game ended.
+    pub land: Vec<u32>,
+    pub armies: Vec<u32>,
+}
+
+/// Plays a bot for every config in `bots` against each other on `map`, which has a
+/// general for every bot, until one is left or `max_turns` have been played. There is no
+/// server clock to keep up with, so every bot searches for its full time budget.
+pub fn play(map: &Map, bots: &[StrategyConfig], max_turns: usize) -> MatchResult {
+    assert_eq!(map.generals.len(), bots.len(), "The map needs a general for every bot");
+
+    let mut state = map.to_state();
+    let usernames: Vec<String> = (0..bots.len()).map(|i| format!("Bot {}", i)).collect();
+    let mut seats: Vec<(Box<dyn Strategy>, Observer, Game, TimeManager)> = vec![];
+    for (player, config) in bots.iter().enumerate() {
+        let mut game = Game::new();
+        game.handle_game_start(&GameStart {
+            player_index: player,
+            replay_id: "arena".to_string(),
+            chat_room: "arena".to_string(),
+            team_chat_room: None,
+            usernames: usernames.clone(),
+            teams: (1..bots.len() + 1).collect(),
+        });
+        let mut bot = strategy::create(config);
+        bot.game_start(&game);
+        seats.push((bot, Observer::new(), game, TimeManager::new(config)));
+    }
+
+    while !is_over(&state, max_turns) {
+        let mut actions = vec![Action::none(); bots.len()];
+        for (player, seat) in seats.iter_mut().enumerate() {
+            if !state.is_alive(player) {
+                continue;
+            }
+            let (ref mut bot, ref mut observer, ref mut game, ref timer) = *seat;
+            game.handle_game_update(&observer.update(&state, player))
+                .expect("The referee sends well formed updates");
+            bot.observe(game);
+            let action = bot.next_move(game, timer.deadline(game, precise_time_ns()));
+            if !action.is_empty() {
+                let width = game.width;
+                game.record_attack(action.src_y() * width + action.src_x(),
+                                   action.dst_y() * width + action.dst_x());
+            }
+            actions[player] = action;
+        }
+        state.apply_actions(&actions);
+    }
+
+    let winner = leader(&state);
+    for (player, seat) in seats.iter_mut().enumerate() {
+        seat.0.game_end(winner == Some(player));
+    }
+    MatchResult {
+        winner,
+        turns: state.turn(),
+        land: (0..bots.len()).map(|p| state.land(p)).collect(),
+        armies: (0..bots.len()).map(|p| state.score(p)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arena::{self, diff, view, Observer};
+    use config::StrategyConfig;
+    use game::Game;
+    use mapgen::Map;
+    use config::BotKind;
+    use state::Action;
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff::<i32>(&[], &[5, 6]), vec![0, 2, 5, 6]);
+        assert_eq!(diff(&[1, 2, 3, 4], &[1, 9, 3, 4]), vec![1, 1, 9, 2]);
+        assert_eq!(diff::<usize>(&[3], &[]), vec![0]);
+        assert_eq!(diff::<usize>(&[3], &[3, 7]), vec![1, 1, 7]);
+    }
+
+    #[test]
+    fn test_view() {
+        let state = Map::fixed(7, 7, 2).to_state();
+        let view = view(&state, 0);
+        let size = 49;
+        // Our general at (1, 1), its surroundings visible and the rest fogged.
+        assert_eq!(view.map[2 + 8], 1);
+        assert_eq!(view.map[2 + size + 8], 0);
+        assert_eq!(view.map[2 + size + 16], -1);
+        assert_eq!(view.map[2 + size + 24], -4);
+        assert_eq!(view.map[2 + size + 40], -3);
+        assert_eq!(view.map[2 + 40], 0);
+        assert_eq!(view.generals, vec![8, -1]);
+        assert!(view.cities.is_empty());
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut state = Map::fixed(7, 7, 2).to_state();
+        let mut observer = Observer::new();
+        let mut game = Game::new();
+        game.player_index = 1;
+
+        for _ in 0..4 {
+            game.handle_game_update(&observer.update(&state, 1)).unwrap();
+            state.apply_actions(&[Action::none(), Action::new(false, 5, 5, 0)]);
+        }
+        game.handle_game_update(&observer.update(&state, 1)).unwrap();
+
+        let view = view(&state, 1);
+        assert_eq!(game.turn, 4);
+        assert_eq!(&game.raw_map[..], &view.map[2..]);
+        assert_eq!(game.generals, view.generals);
+        assert_eq!(game.scores, vec![3, 3]);
+    }
+
+    #[test]
+    fn test_play() {
+        let config = StrategyConfig {
+            search_time_ms: 5,
+            horizon: 10,
+            ..StrategyConfig::default()
+        };
+        let random = StrategyConfig {
+            bot: BotKind::Random,
+            ..StrategyConfig::default()
+        };
+        let result = arena::play(&Map::fixed(6, 6, 2), &[config, random], 20);
+        assert_eq!(result.turns, 20);
+        assert!(result.winner.is_some());
+        assert_eq!(result.land.len(), 2);
+        assert!(result.armies.iter().all(|&army| army > 0));
+    }
+}
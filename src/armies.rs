@@ -0,0 +1,198 @@
+//! Enemy armies out of sight. The scores the server sends count every army and tile a
+//! player holds, so whatever we cannot see of them is hiding in the fog. Every hidden
+//! tile holds an army of its own, and the rest are spare armies that could be on their
+//! way to us: most likely around the general, where armies grow, or on land we saw them
+//! hold before.
+//!
+//! Players and tiles are numbered as on the server, like `Game`.
+
+use game::Game;
+
+const FOG: i32 = -3;
+
+/// Share of the spare armies expected around the general rather than on land we have
+/// seen the player hold.
+const GENERAL_SHARE: f64 = 0.5;
+/// Spare armies below this never raise an alert.
+const MIN_MASS: u32 = 10;
+/// Tiles kept in the likely locations of an army.
+const MAX_LOCATIONS: usize = 10;
+
+/// What one player holds out of our sight.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HiddenArmy {
+    pub armies: u32,
+    pub tiles: u32,
+    /// Armies beyond the one every hidden tile holds, free to move.
+    pub spare: u32,
+    /// The likeliest tiles for the spare armies to be on, each with the armies expected
+    /// there, most first.
+    pub locations: Vec<(usize, f64)>,
+    /// Whether the spare armies keep growing and would take our general as it stands.
+    pub massing: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArmyTracker {
+    /// For every player, what they hide. Ours is always empty.
+    hidden: Vec<HiddenArmy>,
+}
+
+impl ArmyTracker {
+    pub fn new() -> Self {
+        ArmyTracker::default()
+    }
+
+    /// Compares the scores of `game` with what it shows. The memory and the general
+    /// estimates of `game` must already hold the latest update.
+    pub fn update(&mut self, game: &Game) {
+        let players = game.alive.len();
+        if self.hidden.len() != players {
+            self.hidden = vec![HiddenArmy::default(); players];
+        }
+
+        let size = game.width * game.height;
+        let defenders = game.generals
+            .get(game.player_index)
+            .and_then(|&idx| if idx >= 0 { Some(game.raw_map[idx as usize]) } else { None })
+            .unwrap_or(0)
+            .max(0) as u32;
+
+        for player in 0..players {
+            if player == game.player_index || !game.alive[player] {
+                self.hidden[player] = HiddenArmy::default();
+                continue;
+            }
+
+            let (mut armies, mut tiles) = (0, 0);
+            for idx in 0..size {
+                if game.raw_map[size + idx] == player as i32 {
+                    armies += game.raw_map[idx].max(0) as u32;
+                    tiles += 1;
+                }
+            }
+            let armies = game.scores[player].saturating_sub(armies);
+            let tiles = game.tiles[player].saturating_sub(tiles);
+            let spare = armies.saturating_sub(tiles);
+            let growing = spare > self.hidden[player].spare;
+
+            self.hidden[player] = HiddenArmy {
+                armies,
+                tiles,
+                spare,
+                locations: locations(game, player, spare),
+                massing: growing && spare >= MIN_MASS && spare > defenders,
+            };
+        }
+    }
+
+    /// What `player` hides from us, if they are in the game.
+    pub fn get(&self, player: usize) -> Option<&HiddenArmy> {
+        self.hidden.get(player)
+    }
+
+    /// The players whose hidden armies are massing.
+    pub fn alerts(&self) -> Vec<usize> {
+        (0..self.hidden.len()).filter(|&player| self.hidden[player].massing).collect()
+    }
+}
+
+/// Spreads `spare` armies of `player` over the fog: part by where their general likely
+/// is, the rest by the armies we last saw on their land now in the fog.
+fn locations(game: &Game, player: usize, spare: u32) -> Vec<(usize, f64)> {
+    if spare == 0 {
+        return vec![];
+    }
+    let size = game.width * game.height;
+    let mut seen = vec![0.0; size];
+    for (idx, sighting) in game.memory.sightings() {
+        if sighting.terrain == player as i32 && game.raw_map[size + idx] == FOG {
+            seen[idx] = sighting.armies.max(1) as f64;
+        }
+    }
+    let total: f64 = seen.iter().sum();
+    let general_share = if total > 0.0 { GENERAL_SHARE } else { 1.0 };
+
+    let mut locations: Vec<(usize, f64)> = (0..size)
+        .map(|idx| {
+            let mut share = general_share * game.enemy_generals.probability(player, idx);
+            if total > 0.0 {
+                share += (1.0 - general_share) * seen[idx] / total;
+            }
+            (idx, spare as f64 * share)
+        })
+        .filter(|&(_, armies)| armies > 0.0)
+        .collect();
+    locations.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+    locations.truncate(MAX_LOCATIONS);
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use arena::Observer;
+    use armies::ArmyTracker;
+    use game::Game;
+    use mapgen::Map;
+
+    /// 11x11 map seen from our general at (1, 1) with 5 armies and our tile below it,
+    /// with an enemy tile of 2 in sight at (1, 3).
+    fn setup() -> (Map, Observer, Game) {
+        let mut map = Map::fixed(11, 11, 2);
+        map.armies[12] = 5;
+        map.terrain[2 * 11 + 1] = 0;
+        map.terrain[3 * 11 + 1] = 1;
+        map.armies[3 * 11 + 1] = 2;
+        let mut observer = Observer::new();
+        let mut game = Game::new();
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+        (map, observer, game)
+    }
+
+    #[test]
+    fn test_hidden() {
+        let (_, _, mut game) = setup();
+        game.scores = vec![6, 32];
+        game.tiles = vec![2, 4];
+        let mut tracker = ArmyTracker::new();
+        tracker.update(&game);
+        let hidden = tracker.get(1).unwrap().clone();
+        assert_eq!((hidden.armies, hidden.tiles, hidden.spare), (30, 3, 27));
+        assert!(hidden.massing);
+        assert_eq!(tracker.alerts(), vec![1]);
+        assert!(hidden.locations.len() <= 10);
+        let likely = game.enemy_generals.most_likely(1, 1)[0].0;
+        assert_eq!(hidden.locations[0].0, likely);
+        assert_eq!(tracker.get(0).unwrap().spare, 0);
+
+        // Spent armies are no threat, and neither are a few.
+        game.scores = vec![6, 20];
+        tracker.update(&game);
+        assert!(tracker.alerts().is_empty());
+        game.scores = vec![6, 8];
+        game.raw_map[12] = 1;
+        tracker.update(&game);
+        game.scores = vec![6, 9];
+        tracker.update(&game);
+        assert_eq!(tracker.get(1).unwrap().spare, 4);
+        assert!(tracker.alerts().is_empty());
+    }
+
+    #[test]
+    fn test_remembered_land() {
+        let (mut map, mut observer, mut game) = setup();
+        // A stack of 20 we saw on the enemy's land goes back into the fog.
+        map.armies[3 * 11 + 1] = 20;
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+        map.terrain[2 * 11 + 1] = -1;
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+
+        game.scores = vec![5, 22];
+        game.tiles = vec![1, 2];
+        let mut tracker = ArmyTracker::new();
+        tracker.update(&game);
+        let hidden = tracker.get(1).unwrap();
+        assert_eq!(hidden.spare, 20);
+        assert_eq!(hidden.locations[0], (3 * 11 + 1, 10.0));
+    }
+}
@@ -0,0 +1,190 @@
+extern crate rusterals;
+extern crate time;
+
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+use rusterals::arena;
+use rusterals::config::{BotKind, StrategyConfig};
+use rusterals::mapgen::{Map, MapSettings, MAX_SIZE};
+
+const USAGE: &str = "Usage: match [options]
+
+Plays bots against each other offline and reports the results.
+
+Options:
+    --bot [NAME:]MS[:HORIZON[:C]]
+                         Adds a bot, mcts unless NAME says otherwise, taking MS
+                         milliseconds per move, optionally with its search horizon and
+                         exploration constant. Repeat for every player, default two
+                         mcts bots.
+    --bot NAME           Adds a bot with default settings: mcts, greedy or random.
+    --size WxH           Map size, random like the server's by default.
+    --seed SEED          Seed of the first map, the following games use the next seeds.
+    --max-turns TURNS    Turn limit after which the largest army wins, default 500.
+    --games N            Number of games to play, default 1.
+    --threads N          Search threads of every bot, default one per core.
+    --opening TURNS      Turns every bot leaves to the opening planner, default 50.";
+
+struct Settings {
+    bots: Vec<StrategyConfig>,
+    size: Option<(usize, usize)>,
+    seed: u64,
+    max_turns: usize,
+    games: usize,
+    threads: Option<usize>,
+    opening_turns: Option<usize>,
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {:?} for {}", value, flag))
+}
+
+fn parse_bot(value: &str) -> Result<StrategyConfig, String> {
+    let mut config = StrategyConfig::default();
+    let mut parts = value.splitn(4, ':').peekable();
+    if let Some(Ok(bot)) = parts.peek().map(|name| BotKind::parse(name)) {
+        config.bot = bot;
+        parts.next();
+    }
+    if let Some(search_time) = parts.next() {
+        config.search_time_ms = parse_number("--bot", search_time)?;
+    }
+    if let Some(horizon) = parts.next() {
+        config.horizon = parse_number("--bot", horizon)?;
+    }
+    if let Some(exploration) = parts.next() {
+        config.exploration = parse_number("--bot", exploration)?;
+    }
+    if parts.next().is_some() || config.horizon <= 0 || config.exploration.is_nan() ||
+       config.exploration < 0.0 {
+        return Err(format!("Invalid value {:?} for --bot", value));
+    }
+    Ok(config)
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let mut settings = Settings {
+        bots: vec![],
+        size: None,
+        seed: time::get_time().sec as u64,
+        max_turns: 500,
+        games: 1,
+        threads: None,
+        opening_turns: None,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let value = args.get(i + 1).ok_or_else(|| USAGE.to_string())?;
+        match flag {
+            "--bot" => settings.bots.push(parse_bot(value)?),
+            "--size" => {
+                let mut parts = value.split('x').map(|n| n.parse::<usize>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height)), None) => {
+                        settings.size = Some((width, height))
+                    }
+                    _ => return Err(format!("Invalid map size {:?}", value)),
+                }
+            }
+            "--seed" => settings.seed = parse_number(flag, value)?,
+            "--max-turns" => settings.max_turns = parse_number(flag, value)?,
+            "--games" => settings.games = parse_number(flag, value)?,
+            "--threads" => settings.threads = Some(parse_number(flag, value)?),
+            "--opening" => settings.opening_turns = Some(parse_number(flag, value)?),
+            _ => return Err(USAGE.to_string()),
+        }
+        i += 2;
+    }
+
+    if settings.bots.is_empty() {
+        let bot = StrategyConfig {
+            search_time_ms: 100,
+            ..StrategyConfig::default()
+        };
+        settings.bots = vec![bot.clone(), bot];
+    }
+    if let Some(threads) = settings.threads {
+        if threads == 0 {
+            return Err("Bots need at least one search thread".to_string());
+        }
+        for bot in &mut settings.bots {
+            bot.threads = threads;
+        }
+    }
+    if let Some(turns) = settings.opening_turns {
+        for bot in &mut settings.bots {
+            bot.opening_turns = turns;
+        }
+    }
+    if settings.bots.len() < 2 {
+        return Err("Matches need at least 2 bots".to_string());
+    }
+    if let Some((width, height)) = settings.size {
+        if width * height < 4 * settings.bots.len() || width > MAX_SIZE || height > MAX_SIZE {
+            return Err(format!("Map sizes go up to {}x{} and need room for every bot",
+                               MAX_SIZE,
+                               MAX_SIZE));
+        }
+    }
+    Ok(settings)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let settings = match parse_args(&args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut wins = vec![0; settings.bots.len()];
+    for game in 0..settings.games {
+        let seed = settings.seed + game as u64;
+        let map = Map::generate(&MapSettings {
+                                    players: settings.bots.len(),
+                                    size: settings.size,
+                                    ..MapSettings::default()
+                                },
+                                seed);
+        let result = arena::play(&map, &settings.bots, settings.max_turns);
+
+        match result.winner {
+            Some(winner) => {
+                wins[winner] += 1;
+                println!("Game {} (seed {}): bot {} won after {} turns.",
+                         game + 1,
+                         seed,
+                         winner,
+                         result.turns);
+            }
+            None => {
+                println!("Game {} (seed {}): no winner after {} turns.",
+                         game + 1,
+                         seed,
+                         result.turns)
+            }
+        }
+        for (player, bot) in settings.bots.iter().enumerate() {
+            println!("  Bot {} ({:?}, {}ms, horizon {}, exploration {}): land {}, army {}.",
+                     player,
+                     bot.bot,
+                     bot.search_time_ms,
+                     bot.horizon,
+                     bot.exploration,
+                     result.land[player],
+                     result.armies[player]);
+        }
+    }
+
+    if settings.games > 1 {
+        for (player, count) in wins.iter().enumerate() {
+            println!("Bot {} won {} of {} games.", player, count, settings.games);
+        }
+    }
+}
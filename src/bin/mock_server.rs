@@ -0,0 +1,66 @@
+extern crate rusterals;
+
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+use rusterals::mock_server::{self, MockSettings};
+
+const USAGE: &str = "Usage: mock_server [options]
+
+Options:
+    --listen ADDR        Address to serve on, default 127.0.0.1:8080.
+    --size WxH           Map size, default 12x12.
+    --turn-ms MS         Milliseconds per turn, default 500.
+    --max-turns TURNS    Turn limit after which the largest army wins.
+    --ffa-players N      Players needed to start an FFA game.";
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {:?} for {}", value, flag))
+}
+
+fn parse_args(args: &[String]) -> Result<(String, MockSettings), String> {
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut settings = MockSettings::default();
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let value = args.get(i + 1).ok_or_else(|| USAGE.to_string())?;
+        match flag {
+            "--listen" => addr = value.clone(),
+            "--size" => {
+                let mut parts = value.split('x').map(|n| n.parse::<usize>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height)), None) => {
+                        settings.width = width;
+                        settings.height = height;
+                    }
+                    _ => return Err(format!("Invalid map size {:?}", value)),
+                }
+            }
+            "--turn-ms" => settings.turn_ms = parse_number(flag, value)?,
+            "--max-turns" => settings.max_turns = parse_number(flag, value)?,
+            "--ffa-players" => settings.ffa_players = parse_number(flag, value)?,
+            _ => return Err(USAGE.to_string()),
+        }
+        i += 2;
+    }
+    Ok((addr, settings))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (addr, settings) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = mock_server::run(settings, &addr) {
+        eprintln!("Mock server failed: {}", e);
+        process::exit(1);
+    }
+}
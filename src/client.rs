@@ -0,0 +1,365 @@
+use ws::{connect, Handler, Sender, Result, Message, CloseCode};
+use ws::util::Token;
+use json::JsonValue;
+use time::precise_time_ns;
+
+use config::{Config, QueueMode};
+use events::{EventError, ServerEvent};
+use game::Game;
+use protocol::{self, OpenInfo, Packet, SocketPacket};
+use strategy;
+use supervisor::Outcome;
+use timing::TimeManager;
+use worker::SearchWorker;
+
+const PING_TOKEN: Token = Token(0);
+const REJOIN_TOKEN: Token = Token(1);
+/// Set off by the search worker when a move is ready.
+const MOVE_TOKEN: Token = Token(2);
+
+/// How long to wait for updates of an interrupted game after reconnecting before
+/// giving up on it and queueing again. The bot API has no way to ask for a game back, so
+/// rejoining is passive: the server either resumes sending updates to the user or not.
+const REJOIN_TIMEOUT_MS: u64 = 5_000;
+
+/// Everything that outlives a single websocket connection.
+pub struct Session {
+    connected: bool,
+    in_game: bool,
+    game: Game,
+    worker: SearchWorker,
+    timer: TimeManager,
+    /// The search whose move we are waiting for, if any.
+    pending_search: Option<u64>,
+    replay_id: String,
+    pub games_played: usize,
+    pub games_won: usize,
+}
+
+impl Session {
+    pub fn new(config: &Config) -> Self {
+        Session {
+            connected: false,
+            in_game: false,
+            game: Game::new(),
+            worker: SearchWorker::spawn(strategy::create(&config.strategy)),
+            timer: TimeManager::new(&config.strategy),
+            pending_search: None,
+            replay_id: "".to_string(),
+            games_played: 0,
+            games_won: 0,
+        }
+    }
+
+    pub fn finished(&self, config: &Config) -> bool {
+        config.max_games.is_some_and(|max| self.games_played >= max)
+    }
+
+    fn end_game(&mut self, won: bool) {
+        self.in_game = false;
+        self.game = Game::new();
+        self.timer.reset();
+        self.pending_search = None;
+        self.worker.end_game(won);
+        self.games_played += 1;
+        if won {
+            self.games_won += 1;
+        }
+    }
+}
+
+struct Client<'a> {
+    out: Sender,
+    config: &'a Config,
+    session: &'a mut Session,
+    ping_interval: u64,
+    ping_timeout: u64,
+    ping_sent: Option<u64>,
+}
+
+impl<'a> Client<'a> {
+    fn new(out: Sender, config: &'a Config, session: &'a mut Session) -> Self {
+        Client {
+            out,
+            config,
+            session,
+            ping_interval: protocol::DEFAULT_PING_INTERVAL_MS,
+            ping_timeout: protocol::DEFAULT_PING_TIMEOUT_MS,
+            ping_sent: None,
+        }
+    }
+
+    /// Sends an event to the server. Failing means the connection is already going away,
+    /// which closes it and leaves reconnecting to the supervisor.
+    fn emit(&mut self, data: JsonValue) {
+        if let Err(e) = self.out.send(protocol::encode_event(&data)) {
+            println!("Could not send event: {}", e);
+        }
+    }
+
+    fn schedule(&mut self, ms: u64, token: Token) {
+        if let Err(e) = self.out.timeout(ms, token) {
+            println!("Could not schedule timeout: {}", e);
+        }
+    }
+
+    fn close(&mut self, code: CloseCode) {
+        if let Err(e) = self.out.close(code) {
+            println!("Could not close connection: {}", e);
+        }
+    }
+
+    fn handle_open(&mut self, info: OpenInfo) {
+        println!("Connected with session {}.", info.sid);
+        self.session.connected = true;
+        self.ping_interval = info.ping_interval;
+        self.ping_timeout = info.ping_timeout;
+        let interval = self.ping_interval;
+        self.schedule(interval, PING_TOKEN);
+    }
+
+    fn handle_socket_packet(&mut self, packet: SocketPacket) {
+        match packet {
+            SocketPacket::Connect(_) => {
+                if let Some(username) = self.config.username.clone() {
+                    let user_id = self.config.user_id.clone();
+                    self.emit(array!["set_username", user_id, username]);
+                }
+
+                if self.session.in_game {
+                    // Reconnected mid-game. There is nothing to send to get the game back,
+                    // but the server diffs against what it sent this connection, so start
+                    // the map over and wait for updates to resume.
+                    println!("Waiting to rejoin game {}.", self.session.replay_id);
+                    self.session.game.resync();
+                    self.schedule(REJOIN_TIMEOUT_MS, REJOIN_TOKEN);
+                } else {
+                    self.join_queue();
+                }
+            }
+            SocketPacket::Disconnect(namespace) => {
+                println!("Server disconnected namespace {}.", namespace);
+                self.close(CloseCode::Normal);
+            }
+            SocketPacket::Event { name, args, .. } => {
+                match ServerEvent::decode(&name, &args) {
+                    Ok(event) => self.handle_event(event),
+                    Err(EventError::Unknown(name)) => println!("Ignoring unknown event {}.", name),
+                    Err(e) => println!("Ignoring event: {}", e),
+                }
+            }
+            SocketPacket::Ack { .. } => (),
+            SocketPacket::Error(namespace, data) => {
+                println!("Server error on namespace {}: {}", namespace, data);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: ServerEvent) {
+        match event {
+            ServerEvent::GameStart(data) => {
+                self.session.in_game = true;
+                self.session.replay_id = data.replay_id.clone();
+                println!("Game starting. Replay will be at http://bot.generals.io/replays/{}",
+                         self.session.replay_id);
+                self.session.game.handle_game_start(&data);
+                self.session.worker.start_game(self.session.game.clone());
+            }
+            ServerEvent::GameUpdate(data) => {
+                if self.session.in_game {
+                    self.session.timer.observe_update(data.turn, precise_time_ns());
+                    let massing = self.session.game.hidden_armies.alerts();
+                    if let Err(e) = self.session.game.handle_game_update(&data) {
+                        println!("Ignoring update: {}", e);
+                        return;
+                    }
+                    let hidden = &self.session.game.hidden_armies;
+                    for player in hidden.alerts() {
+                        if !massing.contains(&player) {
+                            println!("Player {} is massing {} armies out of sight.",
+                                     player,
+                                     hidden.get(player).map_or(0, |army| army.spare));
+                        }
+                    }
+                    self.start_search();
+                }
+            }
+            ServerEvent::GameLost { .. } => {
+                println!("Game lost.");
+                self.finish_game(false);
+            }
+            ServerEvent::GameWon => {
+                println!("Game won.");
+                self.finish_game(true);
+            }
+            ServerEvent::ErrorSetUsername(ref error) if !error.is_empty() => {
+                println!("Could not set username: {}", error);
+            }
+            ServerEvent::QueueUpdate(_) |
+            ServerEvent::ChatMessage(_) |
+            ServerEvent::PreGameStart |
+            ServerEvent::GameOver |
+            ServerEvent::Stars(_) |
+            ServerEvent::Rank(_) |
+            ServerEvent::ErrorSetUsername(_) => (),
+        }
+    }
+
+    /// Hands the latest snapshot to the search worker, which wakes us up with
+    /// `MOVE_TOKEN` once it has settled on a move before the time manager's deadline.
+    fn start_search(&mut self) {
+        let deadline = self.session.timer.deadline(&self.session.game, precise_time_ns());
+        let out = self.out.clone();
+        let id = self.session.worker.search(self.session.game.clone(), deadline, move || {
+            // Fails only when the connection is already gone.
+            let _ = out.timeout(0, MOVE_TOKEN);
+        });
+        self.session.pending_search = Some(id);
+    }
+
+    /// Sends the move of the latest search, unless a newer update or the end of the game
+    /// made it stale.
+    fn send_move(&mut self) {
+        let result = match self.session.worker.try_result() {
+            Some(result) => result,
+            None => return,
+        };
+        if !self.session.in_game || self.session.pending_search != Some(result.id) {
+            return;
+        }
+        self.session.pending_search = None;
+
+        let action = result.action;
+        if !action.is_empty() {
+            let width = self.session.game.width;
+            let src = action.src_y() * width + action.src_x();
+            let dst = action.dst_y() * width + action.dst_x();
+            println!("Attacking: {}, {}", src, dst);
+            self.emit(array!["attack", src, dst, action.is_half()]);
+            self.session.game.record_attack(src, dst);
+        }
+    }
+
+    fn finish_game(&mut self, won: bool) {
+        self.session.end_game(won);
+        self.emit(array!["leave_game"]);
+        if self.session.finished(self.config) {
+            println!("Played {} games, won {}.",
+                     self.session.games_played,
+                     self.session.games_won);
+            self.close(CloseCode::Normal);
+        } else {
+            self.join_queue();
+        }
+    }
+
+    fn join_test_room(&mut self) {
+        let room = self.config.room_id.clone();
+        let user_id = self.config.user_id.clone();
+        self.emit(array!["join_private", room.as_str(), user_id]);
+        self.emit(array!["set_force_start", room.as_str(), true]);
+        println!("Waiting for custom game: http://bot.generals.io/games/{}",
+                 room);
+    }
+
+    fn join_ffa(&mut self) {
+        let user_id = self.config.user_id.clone();
+        self.emit(array!["play", user_id]);
+        println!("Waiting for FFA game.");
+    }
+
+    fn join_1v1(&mut self) {
+        let user_id = self.config.user_id.clone();
+        self.emit(array!["join_1v1", user_id]);
+        println!("Waiting for 1v1 game.");
+    }
+
+    fn join_2v2(&mut self) {
+        let team = self.config.room_id.clone();
+        let user_id = self.config.user_id.clone();
+        self.emit(array!["join_team", team.as_str(), user_id]);
+        self.emit(array!["set_force_start", team.as_str(), true]);
+        println!("Waiting for 2v2 game with team {}.", team);
+    }
+
+    fn join_queue(&mut self) {
+        match self.config.queue {
+            QueueMode::Custom => self.join_test_room(),
+            QueueMode::Ffa => self.join_ffa(),
+            QueueMode::OneVsOne => self.join_1v1(),
+            QueueMode::TwoVsTwo => self.join_2v2(),
+        }
+    }
+}
+
+impl<'a> Handler for Client<'a> {
+    fn on_message(&mut self, msg: Message) -> Result<()> {
+        let packet = match protocol::decode(msg.as_text()?) {
+            Ok(packet) => packet,
+            Err(e) => {
+                println!("Ignoring packet: {}", e);
+                return Ok(());
+            }
+        };
+
+        match packet {
+            Packet::Open(info) => self.handle_open(info),
+            Packet::Close => self.out.close(CloseCode::Normal)?,
+            Packet::Ping(data) => self.out.send(protocol::encode_pong(&data))?,
+            Packet::Pong(_) => self.ping_sent = None,
+            Packet::Message(packet) => self.handle_socket_packet(packet),
+            Packet::Upgrade | Packet::Noop => (),
+        }
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, token: Token) -> Result<()> {
+        if token == PING_TOKEN {
+            if let Some(sent) = self.ping_sent {
+                if precise_time_ns() - sent > self.ping_timeout * 1_000_000 {
+                    println!("Server did not answer ping in {}ms.", self.ping_timeout);
+                    return self.out.close(CloseCode::Away);
+                }
+            } else {
+                self.ping_sent = Some(precise_time_ns());
+                self.out.send(protocol::encode_ping())?;
+            }
+            self.out.timeout(self.ping_interval, PING_TOKEN)?;
+        } else if token == MOVE_TOKEN {
+            self.send_move();
+        } else if token == REJOIN_TOKEN && self.session.in_game &&
+                  !self.session.game.initialized {
+            println!("Could not rejoin game {}, queueing again.", self.session.replay_id);
+            self.session.in_game = false;
+            self.session.game = Game::new();
+            self.session.worker.end_game(false);
+            self.emit(array!["leave_game"]);
+            self.join_queue();
+        }
+        Ok(())
+    }
+
+    fn on_close(&mut self, code: CloseCode, reason: &str) {
+        println!("Disconnected {:?} {:?}", code, reason);
+    }
+}
+
+/// Connects to the configured server and plays until the connection closes.
+pub fn run(config: &Config, session: &mut Session) -> Outcome {
+    session.connected = false;
+    let mut session_slot = Some(&mut *session);
+    let result = connect(config.server_url.clone(), |out| {
+        Client::new(out, config, session_slot.take().unwrap())
+    });
+    if let Err(e) = result {
+        println!("Connection failed: {}", e);
+    }
+
+    if session.finished(config) {
+        Outcome::Finished
+    } else if session.connected {
+        Outcome::Disconnected
+    } else {
+        Outcome::Failed
+    }
+}
@@ -0,0 +1,391 @@
+use json;
+use json::JsonValue;
+use std::f64;
+use std::fs::File;
+use std::io::Read;
+use std::thread;
+
+pub const DEFAULT_SERVER_URL: &str = "ws://botws.generals.io/socket.io/?EIO=3&transport=websocket";
+
+pub const USAGE: &str = "Usage: rusterals [options]
+
+Options:
+    --config FILE        Load settings from a JSON config file. Command line
+                         options override values from the file.
+    --server URL         Websocket URL of the server.
+    --user-id ID         Secret user id the bot plays as.
+    --username NAME      Username to register for the user id.
+    --mode MODE          Queue to join: custom, ffa, 1v1 or 2v2.
+    --room ID            Custom game id, or team id in 2v2 mode.
+    --games N            Exit after playing N games.
+    --bot NAME           Bot to play with: mcts, greedy or random.
+    --search-time MS     Time the search may spend on each move.
+    --latency MS         Time a move needs to reach the server.
+    --critical-factor F  Search time multiplier for critical turns.
+    --horizon TURNS      Turn interval the search looks ahead to.
+    --exploration C      UCB1 exploration constant of the search.
+    --threads N          Number of threads searching in parallel.
+    --opening TURNS      Turns played by the opening planner, 0 to leave them
+                         to the bot.
+    --help               Print this message.";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueMode {
+    Custom,
+    Ffa,
+    OneVsOne,
+    TwoVsTwo,
+}
+
+impl QueueMode {
+    pub fn parse(name: &str) -> Result<QueueMode, String> {
+        match name {
+            "custom" => Ok(QueueMode::Custom),
+            "ffa" => Ok(QueueMode::Ffa),
+            "1v1" => Ok(QueueMode::OneVsOne),
+            "2v2" => Ok(QueueMode::TwoVsTwo),
+            _ => Err(format!("Unknown queue mode {:?}", name)),
+        }
+    }
+}
+
+/// The bot implementations a game can be played with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BotKind {
+    Random,
+    Greedy,
+    MonteCarlo,
+}
+
+impl BotKind {
+    pub fn parse(name: &str) -> Result<BotKind, String> {
+        match name {
+            "random" => Ok(BotKind::Random),
+            "greedy" => Ok(BotKind::Greedy),
+            "mcts" => Ok(BotKind::MonteCarlo),
+            _ => Err(format!("Unknown bot {:?}", name)),
+        }
+    }
+}
+
+/// How much each feature of a position counts when the search scores it. Features are
+/// between 0 and 1; negative weights make a feature count against us.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    /// Our share of the land.
+    pub land: f64,
+    /// Our share of the armies.
+    pub army: f64,
+    /// Our share of the cities and generals.
+    pub cities: f64,
+    /// How much of our army stands near our general.
+    pub defense: f64,
+    /// How close our largest army is to a known enemy general.
+    pub attack: f64,
+    /// The part of our land bordering land that is not ours.
+    pub frontier: f64,
+    /// How far visible enemy armies near our general outnumber it.
+    pub threat: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            land: 1.0,
+            army: 0.0,
+            cities: 0.0,
+            defense: 0.0,
+            attack: 0.0,
+            frontier: 0.0,
+            threat: 0.0,
+        }
+    }
+}
+
+impl Weights {
+    pub fn to_vec(&self) -> Vec<f64> {
+        vec![self.land,
+             self.army,
+             self.cities,
+             self.defense,
+             self.attack,
+             self.frontier,
+             self.threat]
+    }
+
+    fn apply_json(&mut self, data: &JsonValue) {
+        let fields = [("land", &mut self.land),
+                      ("army", &mut self.army),
+                      ("cities", &mut self.cities),
+                      ("defense", &mut self.defense),
+                      ("attack", &mut self.attack),
+                      ("frontier", &mut self.frontier),
+                      ("threat", &mut self.threat)];
+        for (name, weight) in fields {
+            if let Some(value) = data[name].as_f64() {
+                *weight = value;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StrategyConfig {
+    /// The bot making the moves. The remaining settings apply to whichever uses them.
+    pub bot: BotKind,
+    /// Wall time the search may spend on a single move, in milliseconds. Moves are cut
+    /// short when they would otherwise miss the next turn.
+    pub search_time_ms: u64,
+    /// Time a move takes to reach the server, kept free before every turn.
+    pub latency_ms: u64,
+    /// Critical turns, such as those just before land growth or with our general under
+    /// threat, may search this many times longer.
+    pub critical_factor: f64,
+    /// The search looks ahead to the next multiple of this many turns.
+    pub horizon: i32,
+    /// Weight of the UCB1 exploration term. Rewards are between 0 and 1, so around
+    /// the square root of two balances exploration with exploitation.
+    pub exploration: f64,
+    /// Threads searching in parallel, each growing a tree of its own.
+    pub threads: usize,
+    /// Turns the opening planner plays before handing over to the bot.
+    pub opening_turns: usize,
+    /// How the search scores the positions it reaches.
+    pub weights: Weights,
+}
+
+impl Default for StrategyConfig {
+    fn default() -> StrategyConfig {
+        StrategyConfig {
+            bot: BotKind::MonteCarlo,
+            search_time_ms: 300,
+            latency_ms: 100,
+            critical_factor: 2.0,
+            horizon: 50,
+            exploration: f64::consts::SQRT_2,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            opening_turns: 50,
+            weights: Weights::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub server_url: String,
+    pub user_id: String,
+    pub username: Option<String>,
+    pub queue: QueueMode,
+    pub room_id: String,
+    pub max_games: Option<usize>,
+    pub strategy: StrategyConfig,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            server_url: DEFAULT_SERVER_URL.to_string(),
+            user_id: String::new(),
+            username: None,
+            queue: QueueMode::Custom,
+            room_id: String::new(),
+            max_games: None,
+            strategy: StrategyConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from command line arguments, excluding the program name.
+    /// A `--config` file is applied first so explicit options always take precedence.
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        if let Some(pos) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(pos + 1).ok_or("Missing value for --config")?;
+            config.load_file(path)?;
+        }
+
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].as_str();
+            if flag == "--help" {
+                return Err(USAGE.to_string());
+            }
+
+            let value = args.get(i + 1).ok_or(format!("Missing value for {}", flag))?;
+            match flag {
+                "--config" => (),
+                "--server" => config.server_url = value.clone(),
+                "--user-id" => config.user_id = value.clone(),
+                "--username" => config.username = Some(value.clone()),
+                "--mode" => config.queue = QueueMode::parse(value)?,
+                "--room" => config.room_id = value.clone(),
+                "--games" => config.max_games = Some(parse_number(flag, value)?),
+                "--bot" => config.strategy.bot = BotKind::parse(value)?,
+                "--search-time" => config.strategy.search_time_ms = parse_number(flag, value)?,
+                "--latency" => config.strategy.latency_ms = parse_number(flag, value)?,
+                "--critical-factor" => {
+                    config.strategy.critical_factor = parse_number(flag, value)?
+                }
+                "--horizon" => config.strategy.horizon = parse_number(flag, value)?,
+                "--exploration" => config.strategy.exploration = parse_number(flag, value)?,
+                "--threads" => config.strategy.threads = parse_number(flag, value)?,
+                "--opening" => config.strategy.opening_turns = parse_number(flag, value)?,
+                _ => return Err(format!("Unknown option {}\n\n{}", flag, USAGE)),
+            }
+            i += 2;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Could not read config file {}: {}", path, e))?;
+        let data = json::parse(&contents)
+            .map_err(|e| format!("Could not parse config file {}: {}", path, e))?;
+        self.apply_json(&data)
+    }
+
+    pub fn apply_json(&mut self, data: &JsonValue) -> Result<(), String> {
+        if let Some(url) = data["server"].as_str() {
+            self.server_url = url.to_string();
+        }
+        if let Some(user_id) = data["user_id"].as_str() {
+            self.user_id = user_id.to_string();
+        }
+        if let Some(username) = data["username"].as_str() {
+            self.username = Some(username.to_string());
+        }
+        if let Some(mode) = data["mode"].as_str() {
+            self.queue = QueueMode::parse(mode)?;
+        }
+        if let Some(room) = data["room"].as_str() {
+            self.room_id = room.to_string();
+        }
+        if let Some(games) = data["games"].as_usize() {
+            self.max_games = Some(games);
+        }
+
+        let strategy = &data["strategy"];
+        if !strategy.is_null() {
+            if let Some(bot) = strategy["bot"].as_str() {
+                self.strategy.bot = BotKind::parse(bot)?;
+            }
+            if let Some(search_time) = strategy["search_time_ms"].as_u64() {
+                self.strategy.search_time_ms = search_time;
+            }
+            if let Some(latency) = strategy["latency_ms"].as_u64() {
+                self.strategy.latency_ms = latency;
+            }
+            if let Some(factor) = strategy["critical_factor"].as_f64() {
+                self.strategy.critical_factor = factor;
+            }
+            if let Some(horizon) = strategy["horizon"].as_i32() {
+                self.strategy.horizon = horizon;
+            }
+            if let Some(exploration) = strategy["exploration"].as_f64() {
+                self.strategy.exploration = exploration;
+            }
+            if let Some(threads) = strategy["threads"].as_usize() {
+                self.strategy.threads = threads;
+            }
+            if let Some(turns) = strategy["opening_turns"].as_usize() {
+                self.strategy.opening_turns = turns;
+            }
+            self.strategy.weights.apply_json(&strategy["weights"]);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.user_id.is_empty() {
+            return Err("A user id is required, pass --user-id or set user_id in the config."
+                .to_string());
+        }
+        if self.room_id.is_empty() &&
+           (self.queue == QueueMode::Custom || self.queue == QueueMode::TwoVsTwo) {
+            return Err("Custom and 2v2 games need a --room id.".to_string());
+        }
+        if self.strategy.horizon <= 0 {
+            return Err("The search horizon must be positive.".to_string());
+        }
+        if self.strategy.threads == 0 {
+            return Err("The search needs at least one thread.".to_string());
+        }
+        if self.strategy.critical_factor.is_nan() || self.strategy.critical_factor < 1.0 {
+            return Err("The critical turn factor must be at least 1.".to_string());
+        }
+        if self.strategy.exploration.is_nan() || self.strategy.exploration < 0.0 {
+            return Err("The exploration constant must not be negative.".to_string());
+        }
+        let weights = self.strategy.weights.to_vec();
+        if weights.iter().any(|w| !w.is_finite()) || weights.iter().all(|&w| w == 0.0) {
+            return Err("Evaluation weights must be finite and not all zero.".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {:?} for {}", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{BotKind, Config, QueueMode};
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args(&args(&["--user-id", "secret", "--mode", "1v1",
+                                               "--search-time", "300"]))
+            .unwrap();
+        assert_eq!(config.user_id, "secret");
+        assert_eq!(config.queue, QueueMode::OneVsOne);
+        assert_eq!(config.strategy.search_time_ms, 300);
+        assert_eq!(config.strategy.horizon, 50);
+    }
+
+    #[test]
+    fn test_requires_room() {
+        assert!(Config::from_args(&args(&["--user-id", "secret"])).is_err());
+        assert!(Config::from_args(&args(&["--user-id", "secret", "--mode", "2v2"])).is_err());
+        assert!(Config::from_args(&args(&["--user-id", "secret", "--room", "abc"])).is_ok());
+    }
+
+    #[test]
+    fn test_apply_json() {
+        let mut config = Config::default();
+        config.apply_json(&object!{
+                "user_id": "secret",
+                "username": "[Bot] Test",
+                "mode": "ffa",
+                "strategy": {
+                    "bot": "random",
+                    "horizon": 25,
+                    "exploration": 0.5,
+                    "threads": 3,
+                    "weights": { "army": 0.5, "threat": -2 }
+                }
+            })
+            .unwrap();
+        assert_eq!(config.username, Some("[Bot] Test".to_string()));
+        assert_eq!(config.queue, QueueMode::Ffa);
+        assert_eq!(config.strategy.bot, BotKind::Random);
+        assert_eq!(config.strategy.horizon, 25);
+        assert_eq!(config.strategy.exploration, 0.5);
+        assert_eq!(config.strategy.threads, 3);
+        assert_eq!(config.strategy.weights.to_vec(),
+                   vec![1.0, 0.5, 0.0, 0.0, 0.0, 0.0, -2.0]);
+        assert_eq!(config.strategy.search_time_ms, 300);
+    }
+}
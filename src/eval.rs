@@ -0,0 +1,300 @@
+//! Scoring positions for the search. Each feature measures one thing about our position
+//! between 0 and 1, and the evaluator weighs them as the config says, so the bot can be
+//! tuned without touching the code.
+
+use analysis::{self, manhattan, DistanceMap, UNREACHABLE};
+use config::Weights;
+use state::{State, TileType};
+
+/// Our armies this close to our general count as defending it.
+const DEFENSE_DISTANCE: usize = 4;
+/// Enemy armies this close to our general threaten it.
+const THREAT_DISTANCE: usize = 4;
+
+/// Scores a position for us, player 0, between 0 and 1.
+pub trait Evaluator: Send + Sync {
+    fn evaluate(&self, state: &State) -> f64;
+}
+
+/// Measures one thing about a position for us, between 0 and 1.
+type Feature = fn(&State) -> f64;
+
+/// Every feature of a position, mostly for looking at what the evaluator sees.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Features {
+    pub land: f64,
+    pub army: f64,
+    pub cities: f64,
+    pub defense: f64,
+    pub attack: f64,
+    pub frontier: f64,
+    pub threat: f64,
+}
+
+impl Features {
+    pub fn new(state: &State) -> Self {
+        Features {
+            land: land(state),
+            army: army(state),
+            cities: cities(state),
+            defense: defense(state),
+            attack: attack(state),
+            frontier: frontier(state),
+            threat: threat(state),
+        }
+    }
+}
+
+fn share<F: Fn(usize) -> u32>(state: &State, amount: F) -> f64 {
+    let total: u32 = (0..state.player_count()).map(&amount).sum();
+    if total == 0 {
+        0.0
+    } else {
+        amount(0) as f64 / total as f64
+    }
+}
+
+fn general(state: &State) -> Option<(usize, usize)> {
+    state.owned_tiles(0)
+        .iter()
+        .find(|tile| state.tile(tile.x, tile.y).kind == TileType::General)
+        .map(|tile| (tile.x, tile.y))
+}
+
+fn is_enemy(state: &State, owner: usize) -> bool {
+    owner != 0 && owner < state.player_count()
+}
+
+/// Our share of all the land.
+pub fn land(state: &State) -> f64 {
+    share(state, |player| state.land(player))
+}
+
+/// Our share of all the armies.
+pub fn army(state: &State) -> f64 {
+    share(state, |player| state.score(player))
+}
+
+/// Our share of all the cities and generals held.
+pub fn cities(state: &State) -> f64 {
+    share(state, |player| state.cities(player))
+}
+
+/// How much of our army stands near our general.
+pub fn defense(state: &State) -> f64 {
+    let general = match general(state) {
+        Some(general) => general,
+        None => return 0.0,
+    };
+    let near: u32 = state.owned_tiles(0)
+        .iter()
+        .filter(|tile| manhattan((tile.x, tile.y), general) <= DEFENSE_DISTANCE)
+        .map(|tile| state.tile(tile.x, tile.y).count)
+        .sum();
+    if state.score(0) == 0 {
+        0.0
+    } else {
+        (near as f64 / state.score(0) as f64).min(1.0)
+    }
+}
+
+/// How close our largest army is to an enemy general we know of, counting cities in the
+/// way as `analysis::weighted` does: 1 next to it, 0 as far as the map allows or when
+/// there is none in reach. Uses the fields the game keeps when it has them for every
+/// general, rather than a search at every leaf.
+pub fn attack(state: &State) -> f64 {
+    let mut generals = vec![];
+    for y in 0..state.height() {
+        for x in 0..state.width() {
+            let tile = state.tile(x, y);
+            if tile.kind == TileType::General && is_enemy(state, tile.owner) {
+                generals.push((x, y));
+            }
+        }
+    }
+    let stack = state.owned_tiles(0).iter().max_by_key(|tile| state.tile(tile.x, tile.y).count);
+    let stack = match stack {
+        Some(stack) if !generals.is_empty() => stack,
+        _ => return 0.0,
+    };
+
+    let cached: Option<Vec<&DistanceMap>> =
+        generals.iter().map(|&(x, y)| state.general_distances(x, y)).collect();
+    let steps = match cached {
+        Some(fields) => {
+            fields.iter().map(|field| field.get(stack.x, stack.y)).min().unwrap_or(UNREACHABLE)
+        }
+        None => analysis::weighted(state, 0, &generals).get(stack.x, stack.y),
+    };
+    if steps == UNREACHABLE {
+        return 0.0;
+    }
+    let span = (state.width() + state.height()) as f64;
+    (1.0 - steps as f64 / span).max(0.0)
+}
+
+/// The part of our land bordering land that is not ours.
+pub fn frontier(state: &State) -> f64 {
+    let owned = state.owned_tiles(0);
+    if owned.is_empty() {
+        return 0.0;
+    }
+    let border = owned.iter()
+        .filter(|tile| {
+            analysis::neighbours(state.width(), state.height(), tile.x, tile.y).any(|(x, y, _)| {
+                let neighbour = state.tile(x, y);
+                neighbour.owner != 0 && !analysis::is_wall(&neighbour.kind)
+            })
+        })
+        .count();
+    border as f64 / owned.len() as f64
+}
+
+/// How far the enemy armies we see near our general outnumber it, against our whole
+/// army.
+pub fn threat(state: &State) -> f64 {
+    let general = match general(state) {
+        Some(general) => general,
+        None => return 0.0,
+    };
+    let mut enemies: u32 = 0;
+    for y in 0..state.height() {
+        for x in 0..state.width() {
+            let tile = state.tile(x, y);
+            if is_enemy(state, tile.owner) && manhattan((x, y), general) <= THREAT_DISTANCE {
+                enemies += tile.count;
+            }
+        }
+    }
+    let defenders = state.tile(general.0, general.1).count;
+    if enemies <= defenders || state.score(0) == 0 {
+        0.0
+    } else {
+        ((enemies - defenders) as f64 / state.score(0) as f64).min(1.0)
+    }
+}
+
+/// The weighted sum of the features, scaled so the worst score the weights allow is 0
+/// and the best is 1. Features without weight are not computed at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightedEvaluator {
+    weights: Weights,
+    min: f64,
+    max: f64,
+}
+
+impl WeightedEvaluator {
+    pub fn new(weights: &Weights) -> Self {
+        let all = weights.to_vec();
+        WeightedEvaluator {
+            weights: weights.clone(),
+            min: all.iter().filter(|&&w| w < 0.0).sum(),
+            max: all.iter().filter(|&&w| w > 0.0).sum(),
+        }
+    }
+}
+
+impl Evaluator for WeightedEvaluator {
+    fn evaluate(&self, state: &State) -> f64 {
+        let features: [(f64, Feature); 7] = [(self.weights.land, land),
+                                             (self.weights.army, army),
+                                             (self.weights.cities, cities),
+                                             (self.weights.defense, defense),
+                                             (self.weights.attack, attack),
+                                             (self.weights.frontier, frontier),
+                                             (self.weights.threat, threat)];
+        let score: f64 = features.iter()
+            .filter(|&&(weight, _)| weight != 0.0)
+            .map(|&(weight, feature)| weight * feature(state))
+            .sum();
+        if self.max > self.min {
+            (score - self.min) / (self.max - self.min)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use analysis::{self, Terrain};
+    use config::Weights;
+    use eval::{self, Evaluator, Features, WeightedEvaluator};
+    use game::Game;
+    use mapgen::Map;
+    use state::State;
+
+    /// 7x7 map with our general at (1, 1) holding 5, ours at (2, 1) with 3, and the
+    /// enemy general at (5, 5) with 2 and an enemy army of 20 at (1, 3).
+    fn game() -> Game {
+        let mut game = Map::fixed(7, 7, 2).to_game();
+        game.raw_map[8] = 5;
+        game.raw_map[9] = 3;
+        game.raw_map[49 + 9] = 0;
+        game.raw_map[40] = 2;
+        game.raw_map[22] = 20;
+        game.raw_map[49 + 22] = 1;
+        game.scores = vec![8, 22];
+        game.tiles = vec![2, 2];
+        game
+    }
+
+    fn state() -> State {
+        State::new(&game())
+    }
+
+    #[test]
+    fn test_features() {
+        let features = Features::new(&state());
+        assert_eq!(features.land, 0.5);
+        assert_eq!(features.army, 8.0 / 30.0);
+        assert_eq!(features.cities, 0.5);
+        assert_eq!(features.defense, 1.0);
+        // The general is 8 steps from our stack, on a 7x7 map.
+        assert_eq!(features.attack, 1.0 - 8.0 / 14.0);
+        assert_eq!(features.frontier, 1.0);
+        assert_eq!(features.threat, 1.0);
+    }
+
+    #[test]
+    fn test_cached_distances() {
+        // The neutral city of 40 at (3, 3) is on the way to the enemy general, first with
+        // a way around it and then walled in so that it is the only way.
+        let mut game = game();
+        for walled in 0..2 {
+            if walled == 1 {
+                for x in (0..7).filter(|&x| x != 3) {
+                    game.raw_map[49 + 3 * 7 + x] = -2;
+                }
+            }
+            game.terrain = Terrain::default();
+            let searched = State::new(&game);
+            game.terrain = Terrain::new(&searched);
+            game.terrain.distances_from(5, 5);
+            let cached = State::new(&game);
+            assert!(searched.general_distances(5, 5).is_none());
+            let field = cached.general_distances(5, 5).unwrap();
+            assert_eq!(*field, analysis::weighted(&searched, 0, &[(5, 5)]));
+            assert_eq!(eval::attack(&cached), eval::attack(&searched));
+        }
+        // Breaking through costs a step and the 40 armies.
+        let state = State::new(&game);
+        assert_eq!(state.general_distances(5, 5).unwrap().get(1, 1), 8 + 40);
+        assert_eq!(eval::attack(&state), 0.0);
+    }
+
+    #[test]
+    fn test_weights() {
+        let state = state();
+        let land = WeightedEvaluator::new(&Weights::default());
+        assert_eq!(land.evaluate(&state), 0.5);
+
+        // A threat of 1 against its worst possible is as bad as it gets.
+        let weights = Weights {
+            land: 1.0,
+            threat: -1.0,
+            ..Weights::default()
+        };
+        assert_eq!(WeightedEvaluator::new(&weights).evaluate(&state), 0.25);
+    }
+}
@@ -0,0 +1,242 @@
+use json::JsonValue;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum EventError {
+    Unknown(String),
+    Malformed(&'static str, String),
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventError::Unknown(ref name) => write!(f, "unknown event {:?}", name),
+            EventError::Malformed(event, ref reason) => {
+                write!(f, "malformed {} event: {}", event, reason)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameStart {
+    pub player_index: usize,
+    pub replay_id: String,
+    pub chat_room: String,
+    pub team_chat_room: Option<String>,
+    pub usernames: Vec<String>,
+    pub teams: Vec<usize>,
+}
+
+/// One player's entry of the `scores` list. The server sorts the list by score, `index`
+/// is the player it belongs to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Score {
+    pub index: usize,
+    pub total: u32,
+    pub tiles: u32,
+    pub dead: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameUpdate {
+    pub turn: i32,
+    pub map_diff: Vec<i32>,
+    pub cities_diff: Vec<usize>,
+    pub generals: Vec<isize>,
+    pub scores: Vec<Score>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueUpdate {
+    pub num_players: usize,
+    pub num_force: usize,
+    pub is_forcing: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub chat_room: String,
+    pub username: Option<String>,
+    pub player_index: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerEvent {
+    GameStart(GameStart),
+    GameUpdate(GameUpdate),
+    GameLost { killer: Option<usize> },
+    GameWon,
+    GameOver,
+    QueueUpdate(QueueUpdate),
+    ChatMessage(ChatMessage),
+    PreGameStart,
+    /// Star ratings per queue, e.g. `("duel", 51.2)`.
+    Stars(Vec<(String, f64)>),
+    /// Leaderboard rank per queue.
+    Rank(Vec<(String, f64)>),
+    /// An empty message means the username was accepted.
+    ErrorSetUsername(String),
+}
+
+impl ServerEvent {
+    pub fn decode(name: &str, args: &[JsonValue]) -> Result<ServerEvent, EventError> {
+        let data = args.first().unwrap_or(&JsonValue::Null);
+        match name {
+            "game_start" => decode_game_start(data).map(ServerEvent::GameStart),
+            "game_update" => decode_game_update(data).map(ServerEvent::GameUpdate),
+            "game_lost" => Ok(ServerEvent::GameLost { killer: data["killer"].as_usize() }),
+            "game_won" => Ok(ServerEvent::GameWon),
+            "game_over" => Ok(ServerEvent::GameOver),
+            "queue_update" => {
+                Ok(ServerEvent::QueueUpdate(QueueUpdate {
+                    num_players: data["numPlayers"].as_usize().unwrap_or(0),
+                    num_force: data["numForce"].as_usize().unwrap_or(0),
+                    is_forcing: data["isForcing"].as_bool().unwrap_or(false),
+                }))
+            }
+            "chat_message" => {
+                let message = args.get(1).unwrap_or(&JsonValue::Null);
+                Ok(ServerEvent::ChatMessage(ChatMessage {
+                    chat_room: string(data, "chat_message", "chat room")?,
+                    username: message["username"].as_str().map(|s| s.to_string()),
+                    player_index: message["playerIndex"].as_usize(),
+                    text: string(&message["text"], "chat_message", "text")?,
+                }))
+            }
+            "pre_game_start" => Ok(ServerEvent::PreGameStart),
+            "stars" => Ok(ServerEvent::Stars(ratings(data))),
+            "rank" => Ok(ServerEvent::Rank(ratings(data))),
+            "error_set_username" => {
+                Ok(ServerEvent::ErrorSetUsername(data.as_str().unwrap_or("").to_string()))
+            }
+            _ => Err(EventError::Unknown(name.to_string())),
+        }
+    }
+}
+
+fn string(value: &JsonValue, event: &'static str, field: &str) -> Result<String, EventError> {
+    value.as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| EventError::Malformed(event, format!("missing {}", field)))
+}
+
+fn numbers<T, F>(value: &JsonValue, event: &'static str, field: &str, convert: F)
+                 -> Result<Vec<T>, EventError>
+    where F: Fn(&JsonValue) -> Option<T>
+{
+    if !value.is_array() {
+        return Err(EventError::Malformed(event, format!("missing {}", field)));
+    }
+    value.members()
+        .map(|v| convert(v).ok_or_else(|| EventError::Malformed(event, format!("bad {}", field))))
+        .collect()
+}
+
+fn ratings(data: &JsonValue) -> Vec<(String, f64)> {
+    data.entries()
+        .filter_map(|(queue, value)| value.as_f64().map(|v| (queue.to_string(), v)))
+        .collect()
+}
+
+fn decode_game_start(data: &JsonValue) -> Result<GameStart, EventError> {
+    Ok(GameStart {
+        player_index: data["playerIndex"]
+            .as_usize()
+            .ok_or_else(|| EventError::Malformed("game_start", "missing playerIndex".to_string()))?,
+        replay_id: string(&data["replay_id"], "game_start", "replay_id")?,
+        chat_room: data["chat_room"].as_str().unwrap_or("").to_string(),
+        team_chat_room: data["team_chat_room"].as_str().map(|s| s.to_string()),
+        usernames: data["usernames"]
+            .members()
+            .map(|name| name.as_str().unwrap_or("").to_string())
+            .collect(),
+        teams: data["teams"].members().filter_map(|team| team.as_usize()).collect(),
+    })
+}
+
+fn decode_game_update(data: &JsonValue) -> Result<GameUpdate, EventError> {
+    let mut scores = vec![];
+    for score in data["scores"].members() {
+        match (score["i"].as_usize(), score["total"].as_u32(), score["tiles"].as_u32()) {
+            (Some(index), Some(total), Some(tiles)) => {
+                scores.push(Score {
+                    index,
+                    total,
+                    tiles,
+                    dead: score["dead"].as_bool().unwrap_or(false),
+                })
+            }
+            _ => return Err(EventError::Malformed("game_update", "bad scores".to_string())),
+        }
+    }
+
+    Ok(GameUpdate {
+        turn: data["turn"]
+            .as_i32()
+            .ok_or_else(|| EventError::Malformed("game_update", "missing turn".to_string()))?,
+        map_diff: numbers(&data["map_diff"], "game_update", "map_diff", |v| v.as_i32())?,
+        cities_diff: numbers(&data["cities_diff"], "game_update", "cities_diff", |v| v.as_usize())?,
+        generals: numbers(&data["generals"], "game_update", "generals", |v| v.as_isize())?,
+        scores,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use events::{ServerEvent, EventError, Score};
+    use json;
+
+    #[test]
+    fn test_decode_game_update() {
+        let data = json::parse(r#"{"turn": 3, "map_diff": [0, 4, 2, 1, 5, -1],
+            "cities_diff": [0, 1, 7], "generals": [0, -1],
+            "scores": [{"total": 5, "tiles": 2, "i": 1, "dead": false},
+                       {"total": 3, "tiles": 1, "i": 0, "dead": true}]}"#)
+            .unwrap();
+        match ServerEvent::decode("game_update", &[data]).unwrap() {
+            ServerEvent::GameUpdate(update) => {
+                assert_eq!(update.turn, 3);
+                assert_eq!(update.map_diff, vec![0, 4, 2, 1, 5, -1]);
+                assert_eq!(update.cities_diff, vec![0, 1, 7]);
+                assert_eq!(update.generals, vec![0, -1]);
+                assert_eq!(update.scores[1],
+                           Score {
+                               index: 0,
+                               total: 3,
+                               tiles: 1,
+                               dead: true,
+                           });
+            }
+            other => panic!("Expected game update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(ServerEvent::decode("new_feature", &[]),
+                   Err(EventError::Unknown("new_feature".to_string())));
+        assert!(ServerEvent::decode("game_update", &[object!{ "turn": 1 }]).is_err());
+        assert!(ServerEvent::decode("game_start", &[object!{ "playerIndex": 0 }]).is_err());
+    }
+
+    #[test]
+    fn test_decode_misc() {
+        assert_eq!(ServerEvent::decode("game_lost", &[object!{ "killer": 2 }]),
+                   Ok(ServerEvent::GameLost { killer: Some(2) }));
+        assert_eq!(ServerEvent::decode("stars", &[object!{ "duel": 50.5 }]),
+                   Ok(ServerEvent::Stars(vec![("duel".to_string(), 50.5)])));
+        match ServerEvent::decode("chat_message",
+                                  &[json::from("room"),
+                                    object!{ "username": "a", "text": "hi" }])
+            .unwrap() {
+            ServerEvent::ChatMessage(message) => {
+                assert_eq!(message.chat_room, "room");
+                assert_eq!(message.username, Some("a".to_string()));
+                assert_eq!(message.text, "hi");
+            }
+            other => panic!("Expected chat message, got {:?}", other),
+        }
+    }
+}
@@ -1,6 +1,15 @@
-use json::JsonValue;
+use std::mem;
 
-#[derive(Debug)]
+use analysis::Terrain;
+use armies::ArmyTracker;
+use events::{EventError, GameStart, GameUpdate, Score};
+use memory::FogMemory;
+use moves::{self, Move, Snapshot};
+use obstacles::ObstacleMap;
+use scouting::GeneralFinder;
+use state::State;
+
+#[derive(Clone, Debug)]
 pub struct Game {
     pub initialized: bool,
     pub raw_map: Vec<i32>,
@@ -16,6 +25,28 @@ pub struct Game {
     pub scores: Vec<u32>,
     pub tiles: Vec<u32>,
     pub alive: Vec<bool>,
+
+    /// What we last saw of every tile, kept up to date with the map.
+    pub memory: FogMemory,
+    /// What the obstacles in the fog are likely to be.
+    pub obstacles: ObstacleMap,
+    /// What moving over the map costs us, with the distances from every enemy general we
+    /// know of.
+    pub terrain: Terrain,
+    /// Where the enemy generals we have not found yet are likely to be.
+    pub enemy_generals: GeneralFinder,
+    /// What the enemies hold out of our sight.
+    pub hidden_armies: ArmyTracker,
+    /// The moves the enemies most likely made since the previous update.
+    pub enemy_moves: Vec<Move>,
+    /// The source and target tile of the move we made since the previous update.
+    pub our_move: Option<(usize, usize)>,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::new()
+    }
 }
 
 impl Game {
@@ -35,106 +66,250 @@ impl Game {
             scores: vec![],
             tiles: vec![],
             alive: vec![],
+
+            memory: FogMemory::new(),
+            obstacles: ObstacleMap::new(),
+            terrain: Terrain::default(),
+            enemy_generals: GeneralFinder::new(),
+            hidden_armies: ArmyTracker::new(),
+            enemy_moves: vec![],
+            our_move: None,
         }
     }
 
-    pub fn handle_game_start(&mut self, data: &JsonValue) {
-        self.player_index = data["playerIndex"].as_usize().unwrap();
+    pub fn handle_game_start(&mut self, data: &GameStart) {
+        self.player_index = data.player_index;
     }
 
-    fn initialize(&mut self, data: &JsonValue) {
-        self.initialized = true;
-        self.width = data["map_diff"][2].as_number().unwrap().into();
-        self.height = data["map_diff"][3].as_number().unwrap().into();
-        self.raw_map = vec![0; self.width * self.height * 2];
-        for i in 4..data["map_diff"].len() {
-            self.raw_map[i - 4] = data["map_diff"][i].as_i32().unwrap();
-        }
+    /// Notes the move we sent, so the next update does not take its effects for an
+    /// enemy's.
+    pub fn record_attack(&mut self, from: usize, to: usize) {
+        self.our_move = Some((from, to));
+    }
+
+    /// Forgets the map so the next update is treated as the first of the game, keeping
+    /// the player index from `game_start`.
+    pub fn resync(&mut self) {
+        self.initialized = false;
+    }
 
-        self.generals = vec![0; data["scores"].len()];
-        for i in 0..data["generals"].len() {
-            self.generals[i] = data["generals"][i].as_isize().unwrap().into();
+    fn update_scores(&mut self, scores: &[Score]) {
+        for score in scores {
+            self.scores[score.index] = score.total;
+            self.tiles[score.index] = score.tiles;
+            self.alive[score.index] = !score.dead;
         }
-        self.patch_cities(&data["cities_diff"]);
-        self.scores = vec![0; data["scores"].len()];
-        self.tiles = vec![0; data["scores"].len()];
-        self.alive = vec![false; data["scores"].len()];
     }
 
-    fn patch_map(&mut self, patch: &JsonValue) {
-        let mut idx: isize = -2;
-        let mut patch_idx: usize = 0;
-        loop {
-            idx += patch[patch_idx].as_isize().unwrap();
-            patch_idx += 1;
-            if patch_idx >= patch.len() {
-                break;
-            }
+    /// Applies an update. The first of a game, or the first after `resync`, carries the
+    /// whole map and the later ones patch it. Updates that do not fit the game so far are
+    /// rejected without changing anything.
+    pub fn handle_game_update(&mut self, data: &GameUpdate) -> Result<(), EventError> {
+        // The server diffs the map with its size in front.
+        let mut old_map = vec![];
+        let mut old_cities: &[usize] = &[];
+        if self.initialized {
+            old_map = vec![self.width as i32, self.height as i32];
+            old_map.extend_from_slice(&self.raw_map);
+            old_cities = &self.cities;
+        }
+        let map = patch(&old_map, &data.map_diff, |v| if v >= 0 { Some(v as usize) } else { None })
+            .ok_or_else(|| malformed("map_diff does not fit the map"))?;
+        let cities = patch(old_cities, &data.cities_diff, Some)
+            .ok_or_else(|| malformed("cities_diff does not fit the cities"))?;
 
-            let patches = patch[patch_idx].as_usize().unwrap();
-            for i in 0..patches {
-                self.raw_map[idx as usize + i] = patch[patch_idx + 1 + i].as_i32().unwrap();
-            }
-            patch_idx += patches + 1;
-            idx += patches as isize;
+        if map.len() < 2 || map[0] <= 0 || map[1] <= 0 {
+            return Err(malformed("map has no size"));
+        }
+        let (width, height) = (map[0] as usize, map[1] as usize);
+        let size = width.checked_mul(height).ok_or_else(|| malformed("map is too large"))?;
+        if map.len() - 2 != 2 * size {
+            return Err(malformed("map does not match its size"));
+        }
+        if self.initialized && (width, height) != (self.width, self.height) {
+            return Err(malformed("map changed size"));
+        }
+        if cities.iter().any(|&city| city >= size) {
+            return Err(malformed("city outside the map"));
+        }
+
+        let players = if self.initialized { self.scores.len() } else { data.scores.len() };
+        if data.generals.len() > players {
+            return Err(malformed("more generals than players"));
+        }
+        if data.generals.iter().any(|&general| general < -1 || general >= size as isize) {
+            return Err(malformed("general outside the map"));
+        }
+        if data.scores.iter().any(|score| score.index >= players) {
+            return Err(malformed("score of an unknown player"));
+        }
 
-            if patch_idx >= patch.len() {
-                break;
+        let before = if self.initialized { Some(Snapshot::new(self)) } else { None };
+        self.our_move = None;
+        if !self.initialized {
+            self.initialized = true;
+            self.width = width;
+            self.height = height;
+            self.generals = vec![-1; players];
+            self.scores = vec![0; players];
+            self.tiles = vec![0; players];
+            self.alive = vec![false; players];
+        }
+        self.turn = data.turn;
+        self.raw_map = map[2..].to_vec();
+        self.cities = cities;
+        self.generals[..data.generals.len()].copy_from_slice(&data.generals);
+        self.update_scores(&data.scores);
+
+        let mut memory = mem::take(&mut self.memory);
+        memory.update(self);
+        self.memory = memory;
+        let mut obstacles = mem::take(&mut self.obstacles);
+        obstacles.update(self);
+        self.obstacles = obstacles;
+        let mut terrain = mem::take(&mut self.terrain);
+        terrain.update(&State::new(self));
+        for (player, &general) in self.generals.iter().enumerate() {
+            if player != self.player_index && general >= 0 {
+                let general = general as usize;
+                terrain.distances_from(general % width, general / width);
             }
         }
+        self.terrain = terrain;
+        let mut enemy_generals = mem::take(&mut self.enemy_generals);
+        enemy_generals.update(self);
+        self.enemy_generals = enemy_generals;
+        let mut hidden_armies = mem::take(&mut self.hidden_armies);
+        hidden_armies.update(self);
+        self.hidden_armies = hidden_armies;
+        self.enemy_moves = before.map_or_else(Vec::new, |before| moves::infer(&before, self));
+        Ok(())
     }
+}
 
-    fn patch_cities(&mut self, patch: &JsonValue) {
-        let mut new_cities: Vec<usize> = Vec::new();
-        let mut idx: usize = 0;
-        let mut patch_idx: usize = 0;
-        loop {
-            let matching = patch[patch_idx].as_usize().unwrap();
-            for i in 0..matching {
-                new_cities.push(self.cities[idx + i])
-            }
-            patch_idx += 1;
-            if patch_idx >= patch.len() {
-                break;
-            }
-            idx += matching;
+fn malformed(reason: &str) -> EventError {
+    EventError::Malformed("game_update", reason.to_string())
+}
 
-            let mismatching = patch[patch_idx].as_usize().unwrap();
-            for i in 0..mismatching {
-                new_cities.push(patch[patch_idx + 1 + i].as_usize().unwrap());
-            }
-            patch_idx += mismatching + 1;
-            idx += mismatching;
+/// Applies a diff in the server's format to `old`: alternating counts of values kept and
+/// of values replaced, the latter followed by the new values. `None` if the diff is empty
+/// or reaches past either list.
+fn patch<T, F>(old: &[T], diff: &[T], count: F) -> Option<Vec<T>>
+    where T: Copy,
+          F: Fn(T) -> Option<usize>
+{
+    if diff.is_empty() {
+        return None;
+    }
+    let mut new = Vec::with_capacity(old.len());
+    let mut i = 0;
+    let mut keep = true;
+    while i < diff.len() {
+        let n = count(diff[i])?;
+        i += 1;
+        let end = new.len().checked_add(n)?;
+        if keep {
+            new.extend_from_slice(old.get(new.len()..end)?);
+        } else {
+            new.extend_from_slice(diff.get(i..i.checked_add(n)?)?);
+            i += n;
+        }
+        keep = !keep;
+    }
+    Some(new)
+}
 
-            if patch_idx >= patch.len() {
-                break;
-            }
+#[cfg(test)]
+mod tests {
+    use events::{EventError, GameUpdate, Score};
+    use game::Game;
+
+    /// The first update of a 2x1 map for two players: a general of ours with 3 armies
+    /// next to a city of 40, with the other player already out.
+    fn first() -> GameUpdate {
+        GameUpdate {
+            turn: 1,
+            map_diff: vec![0, 6, 2, 1, 3, 40, 0, -1],
+            cities_diff: vec![0, 1, 1],
+            generals: vec![0, -1],
+            scores: vec![Score {
+                             index: 0,
+                             total: 3,
+                             tiles: 1,
+                             dead: false,
+                         },
+                         Score {
+                             index: 1,
+                             total: 0,
+                             tiles: 0,
+                             dead: true,
+                         }],
         }
+    }
 
-        self.cities = new_cities;
+    fn malformed(game: &mut Game, update: &GameUpdate) -> bool {
+        matches!(game.handle_game_update(update), Err(EventError::Malformed("game_update", _)))
     }
 
-    fn update_scores(&mut self, scores: &JsonValue) {
-        for i in 0..scores.len() {
-            self.scores[i] = scores[i]["total"].as_u32().unwrap();
-            self.tiles[i] = scores[i]["tiles"].as_u32().unwrap();
-            self.alive[i] = scores[i]["dead"].as_bool().unwrap();
-        }
+    #[test]
+    fn test_update() {
+        let mut game = Game::new();
+        game.handle_game_update(&first()).unwrap();
+        assert_eq!((game.width, game.height), (2, 1));
+        assert_eq!(game.raw_map, vec![3, 40, 0, -1]);
+        assert_eq!(game.cities, vec![1]);
+        assert_eq!(game.generals, vec![0, -1]);
+        assert_eq!((game.scores.clone(), game.alive.clone()), (vec![3, 0], vec![true, false]));
+
+        // The general grows, and the city is ours.
+        let mut update = first();
+        update.turn = 2;
+        update.map_diff = vec![2, 2, 4, 39, 1, 1, 0];
+        update.cities_diff = vec![1];
+        game.handle_game_update(&update).unwrap();
+        assert_eq!(game.raw_map, vec![4, 39, 0, 0]);
+        assert_eq!(game.cities, vec![1]);
+        assert_eq!(game.turn, 2);
+        assert_eq!(game.terrain.cached(), 0);
+
+        // The distances from an enemy general are kept once we see it.
+        update.turn = 3;
+        update.map_diff = vec![5, 1, 1];
+        update.generals = vec![0, 1];
+        game.handle_game_update(&update).unwrap();
+        assert_eq!(game.terrain.cached_distances(1, 0).unwrap().get(0, 0), 1);
     }
 
-    pub fn handle_game_update(&mut self, data: &JsonValue) {
-        if !self.initialized {
-            self.initialize(data);
-            self.update_scores(&data["scores"]);
-            return;
+    #[test]
+    fn test_malformed() {
+        let mut game = Game::new();
+        let cases: Vec<fn(&mut GameUpdate)> = vec![|u| u.map_diff = vec![0, 1, 2],
+                                                   |u| u.map_diff = vec![0, 4, 2, 1, 3, 40],
+                                                   |u| u.map_diff = vec![],
+                                                   |u| u.cities_diff = vec![],
+                                                   |u| u.cities_diff = vec![0, 1, 2],
+                                                   |u| u.generals = vec![0, -1, 1],
+                                                   |u| u.generals = vec![2],
+                                                   |u| u.scores[0].index = 2];
+        for case in cases {
+            let mut update = first();
+            case(&mut update);
+            assert!(malformed(&mut game, &update), "{:?}", update);
+            assert!(!game.initialized);
         }
 
-        self.turn = data["turn"].as_i32().unwrap();
-        self.patch_map(&data["map_diff"]);
-        self.patch_cities(&data["cities_diff"]);
-        for i in 0..data["generals"].len() {
-            self.generals[i] = data["generals"][i].as_isize().unwrap();
+        // Later diffs must fit what came before, and a bad one changes nothing.
+        game.handle_game_update(&first()).unwrap();
+        let cases: Vec<fn(&mut GameUpdate)> = vec![|u| u.map_diff = vec![7],
+                                                   |u| u.map_diff = vec![2, 9, 1],
+                                                   |u| u.map_diff = vec![0, 6, 3, 1, 0, 0, 0, 0],
+                                                   |u| u.cities_diff = vec![2]];
+        for case in cases {
+            let mut update = first();
+            update.turn = 2;
+            case(&mut update);
+            assert!(malformed(&mut game, &update), "{:?}", update);
+            assert_eq!((game.turn, game.raw_map.clone()), (1, vec![3, 40, 0, -1]));
         }
-        self.update_scores(&data["scores"]);
     }
 }
@@ -0,0 +1,202 @@
+//! Gathering: bringing the armies of many tiles to one in a few turns. Armies only travel
+//! over our own land, so the tiles form a tree rooted at the target, each moving all but
+//! one army to its parent. A tile delivers `count - 1` armies whatever its depth, as long
+//! as everything between it and the target moves too, so the plan is the connected part
+//! of the tree with the most armies that fits in the turn budget.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use analysis::neighbours;
+use state::{Action, State, DX, DY};
+
+/// The moves of a gather, in the order to make them, and the armies they deliver.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GatherPlan {
+    pub moves: Vec<Action>,
+    pub armies: u32,
+}
+
+/// A tile of the gather tree.
+struct Node {
+    x: usize,
+    y: usize,
+    /// Index of the node this one moves to, `None` for the target.
+    parent: Option<usize>,
+    children: Vec<usize>,
+    depth: usize,
+}
+
+/// Plans the gather of `player`'s armies to `target` in at most `turns` moves, one a
+/// turn. The target need not be ours, in which case the armies end up attacking it.
+pub fn plan(state: &State, player: usize, target: (usize, usize), turns: usize) -> GatherPlan {
+    if turns == 0 {
+        return GatherPlan::default();
+    }
+    let nodes = tree(state, player, target);
+    let value = |node: &Node| state.tile(node.x, node.y).count.saturating_sub(1);
+
+    // best[v][j]: the most armies j tiles of the subtree of v deliver, v included.
+    // splits[v][c][j]: how many of those j come from child c, for rebuilding the plan.
+    let mut best: Vec<Vec<u32>> = vec![vec![]; nodes.len()];
+    let mut splits: Vec<Vec<Vec<usize>>> = vec![vec![]; nodes.len()];
+    for v in (0..nodes.len()).rev() {
+        // The target does not move, so it does not count against the budget.
+        let cap = if v == 0 { turns + 1 } else { turns };
+        let mut table = vec![0, if v == 0 { 0 } else { value(&nodes[v]) }];
+        for &c in &nodes[v].children {
+            let child = &best[c];
+            let size = (table.len() + child.len() - 1).min(cap + 1);
+            let mut merged = table.clone();
+            merged.resize(size, 0);
+            let mut split = vec![0; size];
+            for j in 1..table.len() {
+                for k in 1..child.len() {
+                    // Counts beyond the table so far can only be reached with the child.
+                    let unset = j + k >= table.len() && split.get(j + k) == Some(&0);
+                    if j + k < size && (unset || table[j] + child[k] > merged[j + k]) {
+                        merged[j + k] = table[j] + child[k];
+                        split[j + k] = k;
+                    }
+                }
+            }
+            table = merged;
+            splits[v].push(split);
+        }
+        best[v] = table;
+    }
+
+    let root = &best[0];
+    let size = (1..root.len()).max_by_key(|&j| (root[j], Reverse(j))).unwrap_or(1);
+
+    let mut chosen = vec![];
+    let mut stack = vec![(0, size)];
+    while let Some((v, mut count)) = stack.pop() {
+        chosen.push(v);
+        for (i, &c) in nodes[v].children.iter().enumerate().rev() {
+            let taken = splits[v][i][count];
+            if taken > 0 {
+                stack.push((c, taken));
+                count -= taken;
+            }
+        }
+    }
+
+    // Deeper tiles first, so every tile moves once its children have arrived.
+    chosen.retain(|&v| v != 0);
+    chosen.sort_by_key(|&v| Reverse(nodes[v].depth));
+    GatherPlan {
+        moves: chosen.iter().map(|&v| move_to_parent(&nodes, v)).collect(),
+        armies: root[size],
+    }
+}
+
+/// The gather tree: `player`'s tiles reachable from `target` over their own land, each
+/// pointing at its neighbour one step closer. The target is the first node, and every
+/// node comes after its parent.
+fn tree(state: &State, player: usize, target: (usize, usize)) -> Vec<Node> {
+    let (width, height) = (state.width(), state.height());
+    let mut index = vec![None; width * height];
+    let mut nodes = vec![Node {
+                             x: target.0,
+                             y: target.1,
+                             parent: None,
+                             children: vec![],
+                             depth: 0,
+                         }];
+    index[target.1 * width + target.0] = Some(0);
+
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+    while let Some(v) = queue.pop_front() {
+        let (x, y, depth) = (nodes[v].x, nodes[v].y, nodes[v].depth);
+        for (nx, ny, _) in neighbours(width, height, x, y) {
+            if index[ny * width + nx].is_some() || state.tile(nx, ny).owner != player {
+                continue;
+            }
+            let node = nodes.len();
+            index[ny * width + nx] = Some(node);
+            nodes.push(Node {
+                x: nx,
+                y: ny,
+                parent: Some(v),
+                children: vec![],
+                depth: depth + 1,
+            });
+            nodes[v].children.push(node);
+            queue.push_back(node);
+        }
+    }
+    nodes
+}
+
+fn move_to_parent(nodes: &[Node], v: usize) -> Action {
+    let node = &nodes[v];
+    let parent = &nodes[node.parent.expect("The target does not move")];
+    let dir = (0..4)
+        .find(|&k| {
+            node.x as isize + DX[k] == parent.x as isize &&
+            node.y as isize + DY[k] == parent.y as isize
+        })
+        .expect("Parents are neighbours");
+    Action::new(false, node.x, node.y, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use gather::{self, GatherPlan};
+    use game::Game;
+    use mapgen::Map;
+    use state::State;
+
+    /// Open 7x7 map, our general at (1, 1) with one army, a row of ours to its right
+    /// holding 5 and 3, and a column below it holding 2 and 10.
+    fn game() -> Game {
+        let mut map = Map::fixed(7, 7, 2);
+        for terrain in &mut map.terrain {
+            if *terrain == -2 {
+                *terrain = -1;
+            }
+        }
+        for &(idx, armies) in [(8 + 1, 5), (8 + 2, 3), (8 + 7, 2), (8 + 14, 10)].iter() {
+            map.terrain[idx] = 0;
+            map.armies[idx] = armies;
+        }
+        map.to_game()
+    }
+
+    #[test]
+    fn test_budget() {
+        let state = State::new(&game());
+        // Two moves bring more from the column than from the row.
+        let plan = gather::plan(&state, 0, (1, 1), 2);
+        assert_eq!(plan.armies, 10);
+        let moves: Vec<_> = plan.moves
+            .iter()
+            .map(|a| ((a.src_x(), a.src_y()), (a.dst_x(), a.dst_y())))
+            .collect();
+        assert_eq!(moves, vec![((1, 3), (1, 2)), ((1, 2), (1, 1))]);
+
+        // Enough turns for everything, and none at all.
+        let plan = gather::plan(&state, 0, (1, 1), 10);
+        assert_eq!((plan.moves.len(), plan.armies), (4, 16));
+        assert_eq!(gather::plan(&state, 0, (1, 1), 0), GatherPlan::default());
+
+        // Tiles with a single army are still worth passing through.
+        let mut game = game();
+        game.raw_map[15] = 1;
+        let plan = gather::plan(&State::new(&game), 0, (1, 1), 2);
+        assert_eq!((plan.moves.len(), plan.armies), (2, 9));
+    }
+
+    #[test]
+    fn test_delivers() {
+        let mut state = State::new(&game());
+        let plan = gather::plan(&state, 0, (1, 1), 3);
+        assert_eq!(plan.armies, 14);
+        for &action in &plan.moves {
+            assert!(state.apply_move(0, action));
+        }
+        assert_eq!(state.tile(1, 1).count, 1 + 14);
+    }
+}
@@ -0,0 +1,299 @@
+//! A bot without any search, playing a few rules of thumb: expand to the nearest neutral
+//! land in the opening, take whatever is worth taking next to us, and otherwise gather
+//! armies into the largest stack and march it on the nearest enemy territory, city or
+//! unclaimed land. It is the baseline the search has to beat.
+
+use analysis::{self, DistanceMap};
+use game::Game;
+use gather;
+use state::{Action, State, TileType};
+use strategy::Strategy;
+
+/// Turns spent expanding before the bot starts gathering.
+const OPENING_TURNS: usize = 50;
+
+/// Turns a gather into the stack may take.
+const GATHER_TURNS: usize = 8;
+
+/// Armies a stack needs beyond the defenders before marching on a target.
+const ATTACK_MARGIN: u32 = 2;
+
+/// Score of capturing a tile next to us, by what is on it. Zero means not worth a move.
+fn capture_value(state: &State, x: usize, y: usize) -> u32 {
+    let tile = state.tile(x, y);
+    let neutral = state.player_count();
+    match tile.kind {
+        TileType::General if tile.owner != 0 && tile.owner < neutral => 1_000,
+        TileType::City if tile.owner != 0 && tile.owner < neutral => 600,
+        TileType::City if tile.owner == neutral => 500,
+        TileType::Plain | TileType::Fog if tile.owner != 0 && tile.owner < neutral => {
+            100 + tile.count
+        }
+        _ => 0,
+    }
+}
+
+/// Whether `army` can take every city and general held by someone else on `path`.
+fn gets_through(state: &State, path: &[(usize, usize)], army: u32) -> bool {
+    path.iter().all(|&(x, y)| {
+        let tile = state.tile(x, y);
+        let fortified = tile.kind == TileType::City || tile.kind == TileType::General;
+        tile.owner == 0 || !fortified || army > tile.count + 1
+    })
+}
+
+/// The full move from `(x, y)` one step closer to the sources of `distance`, if any.
+fn step_towards(distance: &DistanceMap, x: usize, y: usize) -> Option<Action> {
+    distance.step(x, y).map(|k| Action::new(false, x, y, k))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Greedy;
+
+impl Greedy {
+    pub fn new() -> Self {
+        Greedy
+    }
+
+    /// The move for us, player 0, in `state`.
+    pub fn choose(&self, state: &State) -> Action {
+        let mut actions = vec![];
+        state.legal_actions(0, &mut actions);
+
+        self.capture(state, &actions)
+            .or_else(|| if state.turn() < OPENING_TURNS {
+                self.expand(state, &actions)
+            } else {
+                None
+            })
+            .or_else(|| self.advance(state))
+            .unwrap_or_else(Action::none)
+    }
+
+    /// The most valuable tile we can take right away.
+    fn capture(&self, state: &State, actions: &[Action]) -> Option<Action> {
+        actions.iter()
+            .filter(|action| !action.is_half())
+            .filter_map(|&action| {
+                let attacking = state.tile(action.src_x(), action.src_y()).count - 1;
+                let target = state.tile(action.dst_x(), action.dst_y());
+                let value = capture_value(state, action.dst_x(), action.dst_y());
+                if value > 0 && attacking > target.count {
+                    Some((value, action))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(value, _)| value)
+            .map(|(_, action)| action)
+    }
+
+    /// Takes empty land next to us with the largest army that can, which leaves the
+    /// most behind to take more.
+    fn expand(&self, state: &State, actions: &[Action]) -> Option<Action> {
+        let neutral = state.player_count();
+        actions.iter()
+            .filter(|action| !action.is_half())
+            .filter(|action| {
+                let target = state.tile(action.dst_x(), action.dst_y());
+                target.owner == neutral && target.count == 0 &&
+                (target.kind == TileType::Plain || target.kind == TileType::Fog)
+            })
+            .max_by_key(|action| state.tile(action.src_x(), action.src_y()).count)
+            .cloned()
+    }
+
+    /// Marches our largest stack on the nearest target it can take, or gathers armies
+    /// into it while it cannot.
+    fn advance(&self, state: &State) -> Option<Action> {
+        let stack = state.owned_tiles(0)
+            .iter()
+            .max_by_key(|tile| state.tile(tile.x, tile.y).count)?;
+        let (sx, sy) = (stack.x, stack.y);
+        let army = state.tile(sx, sy).count;
+
+        let targets = self.targets(state, army);
+        if targets.is_empty() {
+            return self.gather(state, sx, sy);
+        }
+        // A general is worth breaking through cities for, and the game keeps the costed
+        // distances to one it knows of.
+        let generals = targets.iter().all(|&(x, y)| state.tile(x, y).kind == TileType::General);
+        let cached = match targets[..] {
+            [(x, y)] if generals => state.general_distances(x, y),
+            _ => None,
+        };
+        let searched;
+        let distance = match cached {
+            Some(distance) => distance,
+            None => {
+                searched = if generals {
+                    analysis::weighted(state, 0, &targets)
+                } else {
+                    analysis::bfs(state, 0, &targets)
+                };
+                &searched
+            }
+        };
+        let weakest = targets.iter()
+            .map(|&(x, y)| state.tile(x, y).count)
+            .min()
+            .unwrap_or(0);
+
+        if army > weakest + ATTACK_MARGIN {
+            // A city on the way too strong for the stack is gathered for first.
+            if gets_through(state, &distance.path_from(sx, sy), army) {
+                if let Some(action) = step_towards(distance, sx, sy) {
+                    return Some(action);
+                }
+            }
+        }
+        self.gather(state, sx, sy)
+    }
+
+    /// Where to go next: the enemy general if we see it, else the enemy territory we
+    /// see, else neutral cities `army` can take, else unclaimed land.
+    fn targets(&self, state: &State, army: u32) -> Vec<(usize, usize)> {
+        let neutral = state.player_count();
+        let mut generals = vec![];
+        let mut enemies = vec![];
+        let mut cities = vec![];
+        let mut land = vec![];
+        for y in 0..state.height() {
+            for x in 0..state.width() {
+                let tile = state.tile(x, y);
+                if tile.owner != 0 && tile.owner < neutral {
+                    if tile.kind == TileType::General {
+                        generals.push((x, y));
+                    }
+                    enemies.push((x, y));
+                } else if tile.owner == neutral {
+                    match tile.kind {
+                        TileType::City if tile.count + ATTACK_MARGIN < army => {
+                            cities.push((x, y))
+                        }
+                        TileType::Plain | TileType::Fog => land.push((x, y)),
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        vec![generals, enemies, cities, land]
+            .into_iter()
+            .find(|targets| !targets.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The next move of the best gather to the stack at `(x, y)`, or else the largest
+    /// army outside it one step towards it, over land we do not hold if need be.
+    fn gather(&self, state: &State, x: usize, y: usize) -> Option<Action> {
+        let plan = gather::plan(state, 0, (x, y), GATHER_TURNS);
+        if plan.armies > 0 {
+            return plan.moves.first().cloned();
+        }
+
+        let distance = analysis::bfs(state, 0, &[(x, y)]);
+        state.owned_tiles(0)
+            .iter()
+            .filter(|tile| (tile.x, tile.y) != (x, y) && state.tile(tile.x, tile.y).count > 1)
+            .filter_map(|tile| {
+                step_towards(&distance, tile.x, tile.y)
+                    .map(|action| (state.tile(tile.x, tile.y).count, action))
+            })
+            .max_by_key(|&(count, _)| count)
+            .map(|(_, action)| action)
+    }
+}
+
+impl Strategy for Greedy {
+    fn next_move(&mut self, game: &Game, _deadline: u64) -> Action {
+        self.choose(&State::new(game))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use greedy::Greedy;
+    use mapgen::Map;
+    use state::State;
+
+    /// 7x7 map with our general at (1, 1) and the enemy's at (5, 5), all in view.
+    fn map() -> Map {
+        Map::fixed(7, 7, 2)
+    }
+
+    #[test]
+    fn test_opening() {
+        let mut game = map().to_game();
+        game.raw_map[8] = 5;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert!(!action.is_empty());
+        assert_eq!((action.src_x(), action.src_y()), (1, 1));
+        assert_eq!(game.raw_map[49 + action.dst_y() * 7 + action.dst_x()], -1);
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut game = map().to_game();
+        game.turn = 60;
+        // Our tile at (4, 5) can take the general at (5, 5) or a plain tile at (4, 4).
+        game.raw_map[5 * 7 + 4] = 10;
+        game.raw_map[49 + 5 * 7 + 4] = 0;
+        game.raw_map[4 * 7 + 4] = 3;
+        game.raw_map[49 + 4 * 7 + 4] = 1;
+        game.tiles = vec![2, 2];
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.src_x(), action.src_y()), (4, 5));
+        assert_eq!((action.dst_x(), action.dst_y()), (5, 5));
+
+        // Without enough armies for the general, the plain tile will do.
+        game.raw_map[5 * 7 + 5] = 20;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.dst_x(), action.dst_y()), (4, 4));
+    }
+
+    #[test]
+    fn test_gather() {
+        let mut game = map().to_game();
+        game.turn = 60;
+        game.raw_map[5 * 7 + 5] = 30;
+        // Our stack of 10 at (1, 1) is too small for the general, so the 6 at (1, 3)
+        // joins it.
+        game.raw_map[8] = 10;
+        game.raw_map[3 * 7 + 1] = 6;
+        game.raw_map[49 + 3 * 7 + 1] = 0;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.src_x(), action.src_y()), (1, 3));
+        assert_eq!((action.dst_x(), action.dst_y()), (1, 2));
+
+        // Once it is large enough, the stack marches on the general.
+        game.raw_map[8] = 40;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.src_x(), action.src_y()), (1, 1));
+        assert!(action.dst_x() == 2 || action.dst_y() == 2);
+    }
+
+    #[test]
+    fn test_city_in_the_way() {
+        let mut game = map().to_game();
+        game.turn = 60;
+        // Mountains along y = 3 leave only the city of 40 at (3, 3) on the way from our
+        // stack at (3, 1) to the general, so our general's 5 armies join the stack first.
+        game.raw_map[8] = 5;
+        for x in (0..7).filter(|&x| x != 3) {
+            game.raw_map[49 + 3 * 7 + x] = -2;
+        }
+        game.raw_map[3 * 7 + 3] = 40;
+        game.raw_map[7 + 3] = 20;
+        game.raw_map[49 + 7 + 3] = 0;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.src_x(), action.src_y()), (1, 1));
+
+        // Once the stack can take the city, it marches through it.
+        game.raw_map[7 + 3] = 50;
+        let action = Greedy::new().choose(&State::new(&game));
+        assert_eq!((action.src_x(), action.src_y()), (3, 1));
+        assert_eq!((action.dst_x(), action.dst_y()), (3, 2));
+    }
+}
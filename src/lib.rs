@@ -0,0 +1,29 @@
+extern crate ws;
+#[macro_use]
+extern crate json;
+extern crate time;
+extern crate rand;
+
+pub mod analysis;
+pub mod arena;
+pub mod armies;
+pub mod client;
+pub mod config;
+pub mod eval;
+pub mod events;
+pub mod game;
+pub mod gather;
+pub mod greedy;
+pub mod mapgen;
+pub mod memory;
+pub mod mock_server;
+pub mod moves;
+pub mod obstacles;
+pub mod opening;
+pub mod protocol;
+pub mod scouting;
+pub mod strategy;
+pub mod state;
+pub mod supervisor;
+pub mod timing;
+pub mod worker;
@@ -0,0 +1,364 @@
+//! Random maps in the style of the generals.io server, for local games and tests. The same
+//! seed always produces the same map.
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::collections::VecDeque;
+
+use game::Game;
+use state::State;
+
+/// Moves pack each coordinate into five bits.
+pub const MAX_SIZE: usize = 32;
+
+const EMPTY: i32 = -1;
+const MOUNTAIN: i32 = -2;
+
+/// Armies defending a neutral city at the start.
+pub const MIN_GARRISON: i32 = 40;
+pub const MAX_GARRISON: i32 = 50;
+
+/// Cities are kept further than this from every general.
+pub const CITY_CLEARANCE: usize = 2;
+
+/// The steps generals start apart on a map of `width` by `height` for `players`: half
+/// the map in a duel, closer with more players. Crowded maps may go below it.
+pub fn general_spacing(width: usize, height: usize, players: usize) -> usize {
+    (width + height) / players.max(1)
+}
+
+#[derive(Clone, Debug)]
+pub struct MapSettings {
+    pub players: usize,
+    /// Width and height, or `None` for a random size in the range the server uses for
+    /// that many players.
+    pub size: Option<(usize, usize)>,
+    /// Fraction of tiles that are mountains.
+    pub mountain_density: f64,
+    /// Fraction of tiles that are neutral cities.
+    pub city_density: f64,
+}
+
+impl Default for MapSettings {
+    fn default() -> MapSettings {
+        MapSettings {
+            players: 2,
+            size: None,
+            mountain_density: 0.2,
+            city_density: 0.04,
+        }
+    }
+}
+
+/// A full view of a starting position: every general with its single army, neutral
+/// cities with their garrisons and the mountains.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Map {
+    pub width: usize,
+    pub height: usize,
+    pub armies: Vec<i32>,
+    pub terrain: Vec<i32>,
+    /// General tile of each player.
+    pub generals: Vec<usize>,
+    pub cities: Vec<usize>,
+}
+
+impl Map {
+    pub fn generate(settings: &MapSettings, seed: u64) -> Map {
+        assert!(settings.players >= 2, "Maps need at least 2 players");
+        // Xorshift never leaves an all zero state, so the seed is mixed with constants.
+        let mut rng = XorShiftRng::from_seed([0x193a_6754,
+                                              seed as u32,
+                                              (seed >> 32) as u32,
+                                              0x9e37_79b9]);
+
+        let (width, height) = settings.size.unwrap_or_else(|| {
+            let min = (16 + settings.players).min(MAX_SIZE - 6);
+            (rng.gen_range(min, min + 7), rng.gen_range(min, min + 7))
+        });
+        assert!(width * height >= 4 * settings.players,
+                "A {}x{} map is too small for {} players",
+                width,
+                height,
+                settings.players);
+        assert!(width <= MAX_SIZE && height <= MAX_SIZE,
+                "Maps are at most {}x{}",
+                MAX_SIZE,
+                MAX_SIZE);
+
+        let size = width * height;
+        let mut map = Map {
+            width,
+            height,
+            armies: vec![0; size],
+            terrain: vec![EMPTY; size],
+            generals: vec![],
+            cities: vec![],
+        };
+
+        for tile in &mut map.terrain {
+            if rng.gen::<f64>() < settings.mountain_density {
+                *tile = MOUNTAIN;
+            }
+        }
+
+        map.place_generals(&mut rng, settings.players);
+        map.connect_generals(&mut rng);
+        map.place_cities(&mut rng, (size as f64 * settings.city_density).round() as usize);
+        map
+    }
+
+    /// Lays out a fixed map with generals in the corners, a mountain pattern and a
+    /// neutral city in the middle. Supports up to four players.
+    pub fn fixed(width: usize, height: usize, players: usize) -> Map {
+        assert!((2..=4).contains(&players), "Fixed maps support 2 to 4 players");
+        assert!(width >= 5 && height >= 5, "Fixed maps must be at least 5x5");
+
+        let size = width * height;
+        let mut map = Map {
+            width,
+            height,
+            armies: vec![0; size],
+            terrain: vec![EMPTY; size],
+            generals: vec![],
+            cities: vec![],
+        };
+
+        let corners = [(1, 1), (width - 2, height - 2), (width - 2, 1), (1, height - 2)];
+        for (player, &(x, y)) in corners.iter().take(players).enumerate() {
+            let idx = y * width + x;
+            map.generals.push(idx);
+            map.terrain[idx] = player as i32;
+            map.armies[idx] = 1;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let near_general = corners.iter().any(|&(gx, gy)| {
+                    (gx as isize - x as isize).abs() <= 1 && (gy as isize - y as isize).abs() <= 1
+                });
+                if !near_general && (x + 3 * y) % 7 == 0 {
+                    map.terrain[y * width + x] = MOUNTAIN;
+                }
+            }
+        }
+
+        let center = (height / 2) * width + width / 2;
+        map.terrain[center] = EMPTY;
+        map.armies[center] = 40;
+        map.cities.push(center);
+        map
+    }
+
+    fn distance(&self, a: usize, b: usize) -> usize {
+        let (ax, ay) = (a % self.width, a / self.width);
+        let (bx, by) = (b % self.width, b / self.width);
+        ax.max(bx) - ax.min(bx) + ay.max(by) - ay.min(by)
+    }
+
+    /// Spreads the generals out as far as the map allows: they start at least
+    /// `general_spacing` apart, and the spacing shrinks each time the placement fails.
+    fn place_generals<T: Rng>(&mut self, rng: &mut T, players: usize) {
+        let mut spacing = general_spacing(self.width, self.height, players);
+        loop {
+            self.generals.clear();
+            for _ in 0..100 * players {
+                let tile = rng.gen_range(0, self.width * self.height);
+                if self.generals.iter().all(|&g| self.distance(g, tile) >= spacing.max(1)) {
+                    self.generals.push(tile);
+                    if self.generals.len() == players {
+                        break;
+                    }
+                }
+            }
+            if self.generals.len() == players {
+                break;
+            }
+            spacing = spacing.saturating_sub(1);
+        }
+
+        for (player, &general) in self.generals.iter().enumerate() {
+            self.terrain[general] = player as i32;
+            self.armies[general] = 1;
+        }
+    }
+
+    /// Tiles reachable from `start` without crossing mountains.
+    fn reachable(&self, start: usize) -> Vec<bool> {
+        let mut seen = vec![false; self.width * self.height];
+        let mut queue = VecDeque::new();
+        seen[start] = true;
+        queue.push_back(start);
+        while let Some(tile) = queue.pop_front() {
+            let (x, y) = (tile % self.width, tile / self.width);
+            let mut neighbours = vec![];
+            if x > 0 {
+                neighbours.push(tile - 1);
+            }
+            if x + 1 < self.width {
+                neighbours.push(tile + 1);
+            }
+            if y > 0 {
+                neighbours.push(tile - self.width);
+            }
+            if y + 1 < self.height {
+                neighbours.push(tile + self.width);
+            }
+            for next in neighbours {
+                if !seen[next] && self.terrain[next] != MOUNTAIN {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Digs a winding path through the mountains from every general cut off from the
+    /// first one.
+    fn connect_generals<T: Rng>(&mut self, rng: &mut T) {
+        let target = self.generals[0];
+        let (tx, ty) = (target % self.width, target / self.width);
+        for i in 1..self.generals.len() {
+            if self.reachable(target)[self.generals[i]] {
+                continue;
+            }
+
+            let (mut x, mut y) = (self.generals[i] % self.width, self.generals[i] / self.width);
+            while (x, y) != (tx, ty) {
+                let horizontal = y == ty || (x != tx && rng.gen::<bool>());
+                if horizontal {
+                    x = if x < tx { x + 1 } else { x - 1 };
+                } else {
+                    y = if y < ty { y + 1 } else { y - 1 };
+                }
+                let tile = y * self.width + x;
+                if self.terrain[tile] == MOUNTAIN {
+                    self.terrain[tile] = EMPTY;
+                }
+            }
+        }
+    }
+
+    /// Puts cities on empty tiles that can be reached, away from the generals.
+    fn place_cities<T: Rng>(&mut self, rng: &mut T, count: usize) {
+        let reachable = self.reachable(self.generals[0]);
+        let mut candidates: Vec<usize> = (0..self.width * self.height)
+            .filter(|&tile| {
+                reachable[tile] && self.terrain[tile] == EMPTY &&
+                self.generals.iter().all(|&g| self.distance(g, tile) > CITY_CLEARANCE)
+            })
+            .collect();
+        rng.shuffle(&mut candidates);
+        candidates.truncate(count);
+        candidates.sort();
+
+        for &city in &candidates {
+            self.armies[city] = rng.gen_range(MIN_GARRISON, MAX_GARRISON + 1);
+        }
+        self.cities = candidates;
+    }
+
+    /// The map in the layout of `Game::raw_map`: armies, then terrain.
+    pub fn raw_map(&self) -> Vec<i32> {
+        let mut raw_map = self.armies.clone();
+        raw_map.extend_from_slice(&self.terrain);
+        raw_map
+    }
+
+    /// A full view of the map at the start of the game, as player 0.
+    pub fn to_game(&self) -> Game {
+        let players = self.generals.len();
+        Game {
+            initialized: true,
+            raw_map: self.raw_map(),
+            player_index: 0,
+            turn: 0,
+            width: self.width,
+            height: self.height,
+            generals: self.generals.iter().map(|&g| g as isize).collect(),
+            cities: self.cities.clone(),
+            scores: vec![1; players],
+            tiles: vec![1; players],
+            alive: vec![true; players],
+            ..Game::new()
+        }
+    }
+
+    /// The engine state at the start of the game, with players numbered as on the map.
+    pub fn to_state(&self) -> State {
+        State::new(&self.to_game())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mapgen::{Map, MapSettings, MOUNTAIN};
+    use state::TileType;
+
+    #[test]
+    fn test_seeded() {
+        let settings = MapSettings::default();
+        assert_eq!(Map::generate(&settings, 7), Map::generate(&settings, 7));
+        assert!(Map::generate(&settings, 7) != Map::generate(&settings, 8));
+    }
+
+    #[test]
+    fn test_generate() {
+        for players in 2..9 {
+            for seed in 0..20 {
+                let settings = MapSettings {
+                    players,
+                    ..MapSettings::default()
+                };
+                let map = Map::generate(&settings, seed);
+                let min = 16 + players;
+                assert!(map.width >= min && map.width <= min + 6);
+                assert!(map.height >= min && map.height <= min + 6);
+                assert_eq!(map.generals.len(), players);
+                assert!(!map.cities.is_empty());
+
+                let reachable = map.reachable(map.generals[0]);
+                for (player, &general) in map.generals.iter().enumerate() {
+                    assert!(reachable[general]);
+                    assert_eq!(map.terrain[general], player as i32);
+                    assert_eq!(map.armies[general], 1);
+                }
+                if players == 2 {
+                    let (a, b) = (map.generals[0], map.generals[1]);
+                    assert!(map.distance(a, b) >= (map.width + map.height) / 2);
+                }
+                for &city in &map.cities {
+                    assert!(map.armies[city] >= 40 && map.armies[city] <= 50);
+                    assert!(map.terrain[city] != MOUNTAIN);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let map = Map::generate(&MapSettings {
+                                    players: 3,
+                                    size: Some((10, 12)),
+                                    ..MapSettings::default()
+                                },
+                                1);
+        assert_eq!((map.width, map.height), (10, 12));
+        assert_eq!(map.raw_map().len(), 2 * 10 * 12);
+
+        let state = map.to_state();
+        assert_eq!(state.player_count(), 3);
+        for (player, &general) in map.generals.iter().enumerate() {
+            let tile = state.tile(general % 10, general / 10);
+            assert_eq!(tile.kind, TileType::General);
+            assert_eq!(tile.owner, player);
+            assert_eq!(state.land(player), 1);
+        }
+        for &city in &map.cities {
+            let tile = state.tile(city % 10, city / 10);
+            assert_eq!(tile.kind, TileType::City);
+            assert_eq!(tile.owner, 3);
+            assert_eq!(tile.count, map.armies[city] as u32);
+        }
+    }
+}
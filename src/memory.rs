@@ -0,0 +1,107 @@
+//! What we last saw of every tile. The server only sends what is in sight, and a tile
+//! going back into fog shows up as plain fog or an obstacle, so without a memory every
+//! army, city and border we scouted is forgotten as soon as we look away.
+
+use game::Game;
+
+const FOG: i32 = -3;
+const FOG_OBSTACLE: i32 = -4;
+
+/// A tile as it was the last time we saw it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sighting {
+    /// Terrain as the server sent it: the owner, or -1 for empty land and -2 for a
+    /// mountain.
+    pub terrain: i32,
+    pub armies: i32,
+    pub city: bool,
+    pub general: bool,
+    pub turn: i32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FogMemory {
+    width: usize,
+    height: usize,
+    tiles: Vec<Option<Sighting>>,
+}
+
+impl FogMemory {
+    pub fn new() -> Self {
+        FogMemory::default()
+    }
+
+    /// Records every tile `game` shows. A map of another size belongs to another game,
+    /// which starts with nothing remembered.
+    pub fn update(&mut self, game: &Game) {
+        if game.width != self.width || game.height != self.height {
+            self.width = game.width;
+            self.height = game.height;
+            self.tiles = vec![None; game.width * game.height];
+        }
+
+        let size = self.width * self.height;
+        for (idx, tile) in self.tiles.iter_mut().enumerate() {
+            let terrain = game.raw_map[size + idx];
+            if terrain == FOG || terrain == FOG_OBSTACLE {
+                continue;
+            }
+            *tile = Some(Sighting {
+                terrain,
+                armies: game.raw_map[idx],
+                city: game.cities.contains(&idx),
+                general: game.generals.contains(&(idx as isize)),
+                turn: game.turn,
+            });
+        }
+    }
+
+    /// The last sighting of the tile at `idx`, if we ever saw it.
+    #[inline]
+    pub fn get(&self, idx: usize) -> Option<&Sighting> {
+        self.tiles.get(idx).and_then(|tile| tile.as_ref())
+    }
+
+    /// Turns since we last saw the tile at `idx` as of `turn`, if we ever did.
+    pub fn age(&self, idx: usize, turn: i32) -> Option<i32> {
+        self.get(idx).map(|sighting| turn - sighting.turn)
+    }
+
+    /// Every tile we have seen, with its index.
+    pub fn sightings(&self) -> impl Iterator<Item = (usize, &Sighting)> {
+        self.tiles.iter().enumerate().filter_map(|(idx, tile)| tile.as_ref().map(|t| (idx, t)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mapgen::Map;
+    use memory::FogMemory;
+
+    #[test]
+    fn test_memory() {
+        let mut game = Map::fixed(7, 7, 2).to_game();
+        game.turn = 3;
+        let mut memory = FogMemory::new();
+        memory.update(&game);
+        assert_eq!(memory.get(40).map(|s| (s.terrain, s.general)), Some((1, true)));
+        assert_eq!(memory.get(24).map(|s| (s.armies, s.city)), Some((40, true)));
+
+        // Fog hides the enemy general and the city, but we remember them.
+        game.turn = 9;
+        game.raw_map[49 + 40] = -3;
+        game.raw_map[40] = 0;
+        game.raw_map[49 + 24] = -4;
+        game.raw_map[24] = 0;
+        game.raw_map[8] = 7;
+        memory.update(&game);
+        assert_eq!(memory.get(40).map(|s| (s.terrain, s.armies, s.turn)), Some((1, 1, 3)));
+        assert_eq!(memory.age(24, 9), Some(6));
+        assert_eq!(memory.get(8).map(|s| (s.armies, s.turn)), Some((7, 9)));
+        assert_eq!(memory.sightings().count(), 49);
+
+        // Another game forgets everything.
+        memory.update(&Map::fixed(6, 6, 2).to_game());
+        assert_eq!(memory.age(40, 0), None);
+    }
+}
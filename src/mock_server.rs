@@ -0,0 +1,681 @@
+//! A local stand-in for the generals.io bot server, speaking the same Engine.IO and
+//! Socket.IO framing and events, so the client can be exercised without a network.
+
+use json::JsonValue;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use ws::{self, CloseCode, Handler, Handshake, Message, Sender, WebSocket};
+use ws::util::Token;
+
+use arena::{self, Observer};
+use mapgen::Map;
+use protocol::{self, Packet, SocketPacket};
+use state::{Action, State};
+
+#[derive(Clone, Debug)]
+pub struct MockSettings {
+    pub width: usize,
+    pub height: usize,
+    /// Milliseconds between turns. The websocket timers tick every 100ms, so shorter
+    /// turns are not any faster.
+    pub turn_ms: u64,
+    /// After this many turns the player with the largest army wins.
+    pub max_turns: usize,
+    /// Players needed before an FFA game starts.
+    pub ffa_players: usize,
+}
+
+impl Default for MockSettings {
+    fn default() -> MockSettings {
+        MockSettings {
+            width: 12,
+            height: 12,
+            turn_ms: 500,
+            max_turns: 1_000,
+            ffa_players: 2,
+        }
+    }
+}
+
+/// The server side of a game: the engine plus every player's queue of pending moves.
+#[derive(Clone, Debug)]
+pub struct MockGame {
+    pub state: State,
+    moves: Vec<VecDeque<Action>>,
+}
+
+impl MockGame {
+    pub fn new(width: usize, height: usize, players: usize) -> Self {
+        MockGame {
+            state: Map::fixed(width, height, players).to_state(),
+            moves: vec![VecDeque::new(); players],
+        }
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.state.player_count()
+    }
+
+    /// Queues a move between two tile indices. Moves between tiles that are not
+    /// neighbours are dropped.
+    pub fn queue_move(&mut self, player: usize, src: usize, dst: usize, half: bool) {
+        let width = self.state.width();
+        let (x, y) = (src % width, src / width);
+        let dir = if dst + 1 == src && x > 0 {
+            0
+        } else if dst + width == src {
+            1
+        } else if dst == src + 1 && x + 1 < width {
+            2
+        } else if dst == src + width {
+            3
+        } else {
+            return;
+        };
+        self.moves[player].push_back(Action::new(half, x, y, dir));
+    }
+
+    pub fn clear_moves(&mut self, player: usize) {
+        self.moves[player].clear();
+    }
+
+    pub fn surrender(&mut self, player: usize) {
+        self.state.surrender(player);
+        self.moves[player].clear();
+    }
+
+    /// Advances one turn, playing each player's first queued move that can still be
+    /// made. Returns `(loser, killer)` for every general captured.
+    pub fn step(&mut self) -> Vec<(usize, usize)> {
+        let mut captured = vec![];
+        for player in self.state.move_order() {
+            if !self.state.is_alive(player) {
+                continue;
+            }
+
+            let alive: Vec<bool> =
+                (0..self.player_count()).map(|p| self.state.is_alive(p)).collect();
+            while let Some(action) = self.moves[player].pop_front() {
+                if self.state.apply_move(player, action) {
+                    break;
+                }
+            }
+            for (loser, was_alive) in alive.into_iter().enumerate() {
+                if was_alive && !self.state.is_alive(loser) {
+                    self.moves[loser].clear();
+                    captured.push((loser, player));
+                }
+            }
+        }
+        self.state.end_turn();
+        captured
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Queue {
+    Custom(String),
+    Ffa,
+    OneVsOne,
+    Team(String),
+}
+
+struct Seat {
+    connection: Option<u32>,
+    observer: Observer,
+}
+
+struct RunningGame {
+    engine: MockGame,
+    seats: Vec<Seat>,
+    replay_id: String,
+    /// The player whose connection times the turns of the game.
+    ticker: Option<u32>,
+}
+
+struct Player {
+    out: Sender,
+    username: String,
+    queue: Option<Queue>,
+    force_start: bool,
+    game: Option<usize>,
+}
+
+struct Lobby {
+    settings: MockSettings,
+    players: HashMap<u32, Player>,
+    games: HashMap<usize, RunningGame>,
+    next_game: usize,
+}
+
+fn emit(out: &Sender, data: JsonValue) {
+    // A send only fails when the connection is already going away.
+    let _ = out.send(protocol::encode_event(&data));
+}
+
+impl Lobby {
+    fn new(settings: MockSettings) -> Self {
+        Lobby {
+            settings,
+            players: HashMap::new(),
+            games: HashMap::new(),
+            next_game: 0,
+        }
+    }
+
+    fn join(&mut self, id: u32, queue: Queue) {
+        if let Some(player) = self.players.get_mut(&id) {
+            if player.game.is_some() {
+                return;
+            }
+            player.queue = Some(queue.clone());
+            player.force_start = false;
+        }
+        self.queue_changed(&queue);
+    }
+
+    fn set_force_start(&mut self, id: u32, force: bool) {
+        let queue = match self.players.get_mut(&id) {
+            Some(player) => {
+                player.force_start = force;
+                player.queue.clone()
+            }
+            None => None,
+        };
+        if let Some(queue) = queue {
+            self.queue_changed(&queue);
+        }
+    }
+
+    fn queue_changed(&mut self, queue: &Queue) {
+        let mut members: Vec<u32> = self.players
+            .iter()
+            .filter(|&(_, p)| p.queue.as_ref() == Some(queue))
+            .map(|(&id, _)| id)
+            .collect();
+        members.sort();
+        let forcing = members.iter().filter(|id| self.players[id].force_start).count();
+
+        for id in &members {
+            emit(&self.players[id].out,
+                 array!["queue_update",
+                        object!{
+                            "numPlayers": members.len(),
+                            "numForce": forcing,
+                            "isForcing": self.players[id].force_start
+                        }]);
+        }
+
+        let ready = match *queue {
+            Queue::Custom(_) | Queue::Team(_) => !members.is_empty() && forcing == members.len(),
+            Queue::OneVsOne => members.len() >= 2,
+            Queue::Ffa => members.len() >= self.settings.ffa_players,
+        };
+        if ready {
+            let wanted = match *queue {
+                Queue::OneVsOne => 2,
+                Queue::Ffa => self.settings.ffa_players,
+                _ => members.len(),
+            };
+            members.truncate(wanted);
+            self.start_game(&members);
+        }
+    }
+
+    fn start_game(&mut self, members: &[u32]) {
+        // Lone players get idle opponents so a game can always start.
+        let players = members.len().max(2);
+        let engine = MockGame::new(self.settings.width, self.settings.height, players);
+        let game_id = self.next_game;
+        self.next_game += 1;
+        let replay_id = format!("mock{}", game_id);
+
+        let mut usernames: Vec<String> = vec![];
+        for i in 0..players {
+            match members.get(i) {
+                Some(id) => usernames.push(self.players[id].username.clone()),
+                None => usernames.push(format!("Idle {}", i)),
+            }
+        }
+
+        let mut seats = vec![];
+        for i in 0..players {
+            let connection = members.get(i).cloned();
+            if let Some(id) = connection {
+                let player = self.players.get_mut(&id).unwrap();
+                player.queue = None;
+                player.force_start = false;
+                player.game = Some(game_id);
+                emit(&player.out, array!["pre_game_start"]);
+                emit(&player.out,
+                     array!["game_start",
+                            object!{
+                                "playerIndex": i,
+                                "replay_id": replay_id.as_str(),
+                                "chat_room": format!("game_{}", game_id),
+                                "usernames": usernames.clone(),
+                                "teams": (1..players + 1).collect::<Vec<usize>>()
+                            }]);
+            }
+            seats.push(Seat {
+                connection,
+                observer: Observer::new(),
+            });
+        }
+
+        self.games.insert(game_id,
+                          RunningGame {
+                              engine,
+                              seats,
+                              replay_id,
+                              ticker: None,
+                          });
+        self.schedule_turn(game_id);
+    }
+
+    fn attack(&mut self, id: u32, src: usize, dst: usize, half: bool) {
+        if let Some((game, seat)) = self.seat_of(id) {
+            self.games.get_mut(&game).unwrap().engine.queue_move(seat, src, dst, half);
+        }
+    }
+
+    fn clear_moves(&mut self, id: u32) {
+        if let Some((game, seat)) = self.seat_of(id) {
+            self.games.get_mut(&game).unwrap().engine.clear_moves(seat);
+        }
+    }
+
+    fn seat_of(&self, id: u32) -> Option<(usize, usize)> {
+        let game_id = self.players.get(&id).and_then(|p| p.game)?;
+        let game = self.games.get(&game_id)?;
+        let seat = game.seats.iter().position(|s| s.connection == Some(id))?;
+        Some((game_id, seat))
+    }
+
+    /// Removes the player from its game, surrendering if it was still playing.
+    fn leave_game(&mut self, id: u32) {
+        let (game_id, seat) = match self.seat_of(id) {
+            Some(found) => found,
+            None => return,
+        };
+        self.players.get_mut(&id).unwrap().game = None;
+
+        let ticking = {
+            let game = self.games.get_mut(&game_id).unwrap();
+            game.seats[seat].connection = None;
+            game.engine.surrender(seat);
+            game.ticker == Some(id)
+        };
+        // Anyone else's timer is still set for the next turn.
+        if ticking && !self.schedule_turn(game_id) {
+            self.games.remove(&game_id);
+        } else {
+            self.check_winner(game_id);
+        }
+    }
+
+    /// Sets the timer for the next turn of a game on the connection of its ticker, or of
+    /// the first player still seated once the ticker is gone. Returns whether a timer
+    /// could be set.
+    fn schedule_turn(&mut self, game_id: usize) -> bool {
+        let game = match self.games.get_mut(&game_id) {
+            Some(game) => game,
+            None => return false,
+        };
+        let seated: Vec<u32> = game.seats.iter().filter_map(|s| s.connection).collect();
+        let ticker = game.ticker.filter(|id| seated.contains(id));
+        for id in ticker.into_iter().chain(seated) {
+            match self.players[&id].out.timeout(self.settings.turn_ms, Token(game_id)) {
+                Ok(()) => {
+                    game.ticker = Some(id);
+                    return true;
+                }
+                Err(e) => println!("Mock server could not time game {}: {}", game.replay_id, e),
+            }
+        }
+        game.ticker = None;
+        false
+    }
+
+    fn disconnect(&mut self, id: u32) {
+        self.leave_game(id);
+        let queue = self.players.remove(&id).and_then(|p| p.queue);
+        if let Some(queue) = queue {
+            self.queue_changed(&queue);
+        }
+    }
+
+    /// Plays one turn of a game. Returns whether the game is still running.
+    fn tick(&mut self, game_id: usize) -> bool {
+        let captured = match self.games.get_mut(&game_id) {
+            Some(game) => game.engine.step(),
+            None => return false,
+        };
+
+        self.send_updates(game_id);
+        for (loser, killer) in captured {
+            let connection = self.games[&game_id].seats[loser].connection;
+            if let Some(id) = connection {
+                emit(&self.players[&id].out,
+                     array!["game_lost", object!{ "killer": killer }]);
+                self.players.get_mut(&id).unwrap().game = None;
+                self.games.get_mut(&game_id).unwrap().seats[loser].connection = None;
+            }
+        }
+
+        if self.check_winner(game_id) {
+            return false;
+        }
+        // A captured ticker hands the timer on, as it would by leaving.
+        if !self.schedule_turn(game_id) {
+            self.games.remove(&game_id);
+            return false;
+        }
+        true
+    }
+
+    fn send_updates(&mut self, game_id: usize) {
+        let game = self.games.get_mut(&game_id).unwrap();
+        for (index, seat) in game.seats.iter_mut().enumerate() {
+            let id = match seat.connection {
+                Some(id) => id,
+                None => continue,
+            };
+            let update = seat.observer.update(&game.engine.state, index);
+            let scores: Vec<JsonValue> = update.scores
+                .iter()
+                .map(|score| {
+                    object!{
+                        "total": score.total,
+                        "tiles": score.tiles,
+                        "i": score.index,
+                        "dead": score.dead
+                    }
+                })
+                .collect();
+            let update = object!{
+                "turn": update.turn,
+                "map_diff": update.map_diff,
+                "cities_diff": update.cities_diff,
+                "generals": update.generals,
+                "scores": scores,
+                "attackIndex": 0
+            };
+            emit(&self.players[&id].out, array!["game_update", update]);
+        }
+    }
+
+    /// Ends the game once a single player is left standing or the turn limit is hit.
+    fn check_winner(&mut self, game_id: usize) -> bool {
+        let winner = {
+            let game = match self.games.get(&game_id) {
+                Some(game) => game,
+                None => return true,
+            };
+            let state = &game.engine.state;
+            if !arena::is_over(state, self.settings.max_turns) {
+                return false;
+            }
+            arena::leader(state)
+        };
+
+        let game = self.games.remove(&game_id).unwrap();
+        println!("Game {} over, winner {:?}.", game.replay_id, winner);
+        for (index, seat) in game.seats.iter().enumerate() {
+            if let Some(id) = seat.connection {
+                let event = if Some(index) == winner {
+                    array!["game_won", object!{}]
+                } else {
+                    array!["game_lost", object!{}]
+                };
+                emit(&self.players[&id].out, event);
+                self.players.get_mut(&id).unwrap().game = None;
+            }
+        }
+        true
+    }
+}
+
+struct ServerConnection {
+    out: Sender,
+    lobby: Rc<RefCell<Lobby>>,
+}
+
+impl ServerConnection {
+    fn handle_event(&mut self, name: &str, args: &[JsonValue]) {
+        let id = self.out.connection_id();
+        let mut lobby = self.lobby.borrow_mut();
+        let user = |i: usize| args.get(i).and_then(|a| a.as_str()).unwrap_or("").to_string();
+
+        match name {
+            "set_username" => {
+                if let Some(player) = lobby.players.get_mut(&id) {
+                    player.username = user(1);
+                }
+                emit(&self.out, array!["error_set_username", ""]);
+            }
+            "join_private" => lobby.join(id, Queue::Custom(user(0))),
+            "join_team" => lobby.join(id, Queue::Team(user(0))),
+            "join_1v1" => lobby.join(id, Queue::OneVsOne),
+            "play" => lobby.join(id, Queue::Ffa),
+            "set_force_start" => {
+                let force = args.get(1).and_then(|a| a.as_bool()).unwrap_or(false);
+                lobby.set_force_start(id, force);
+            }
+            "attack" => {
+                let src = args.first().and_then(|a| a.as_usize());
+                let dst = args.get(1).and_then(|a| a.as_usize());
+                let half = args.get(2).and_then(|a| a.as_bool()).unwrap_or(false);
+                if let (Some(src), Some(dst)) = (src, dst) {
+                    lobby.attack(id, src, dst, half);
+                }
+            }
+            "clear_moves" => lobby.clear_moves(id),
+            "leave_game" => lobby.leave_game(id),
+            _ => println!("Mock server ignoring event {:?}.", name),
+        }
+    }
+}
+
+impl Handler for ServerConnection {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        let id = self.out.connection_id();
+        self.lobby.borrow_mut().players.insert(id,
+                                               Player {
+                                                   out: self.out.clone(),
+                                                   username: format!("Anonymous {}", id),
+                                                   queue: None,
+                                                   force_start: false,
+                                                   game: None,
+                                               });
+        let open = object!{
+            "sid": format!("mock{}", id),
+            "upgrades": [],
+            "pingInterval": protocol::DEFAULT_PING_INTERVAL_MS,
+            "pingTimeout": protocol::DEFAULT_PING_TIMEOUT_MS
+        };
+        self.out.send(format!("0{}", open))?;
+        self.out.send("40")
+    }
+
+    fn on_message(&mut self, msg: Message) -> ws::Result<()> {
+        match protocol::decode(msg.as_text()?) {
+            Ok(Packet::Ping(data)) => self.out.send(protocol::encode_pong(&data)),
+            Ok(Packet::Close) => self.out.close(CloseCode::Normal),
+            Ok(Packet::Message(SocketPacket::Event { name, args, .. })) => {
+                self.handle_event(&name, &args);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                println!("Mock server ignoring packet: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    fn on_timeout(&mut self, token: Token) -> ws::Result<()> {
+        self.lobby.borrow_mut().tick(token.0);
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: CloseCode, _: &str) {
+        self.lobby.borrow_mut().disconnect(self.out.connection_id());
+    }
+}
+
+fn build(settings: MockSettings, addr: &str)
+         -> Result<WebSocket<impl FnMut(Sender) -> ServerConnection>, String> {
+    let lobby = Rc::new(RefCell::new(Lobby::new(settings)));
+    let factory = move |out| {
+        ServerConnection {
+            out,
+            lobby: lobby.clone(),
+        }
+    };
+    let server = WebSocket::new(factory).map_err(|e| e.to_string())?;
+    server.bind(addr).map_err(|e| e.to_string())
+}
+
+/// Serves on `addr` until the process exits.
+pub fn run(settings: MockSettings, addr: &str) -> Result<(), String> {
+    let server = build(settings, addr)?;
+    println!("Mock server listening on {}.", server.local_addr().map_err(|e| e.to_string())?);
+    server.run().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Starts a server on a free local port in a background thread. Returns its address and
+/// a sender that shuts it down.
+pub fn spawn(settings: MockSettings) -> (SocketAddr, Sender) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let server = build(settings, "127.0.0.1:0").unwrap();
+        tx.send((server.local_addr().unwrap(), server.broadcaster())).unwrap();
+        server.run().unwrap();
+    });
+    rx.recv().unwrap()
+}
+
+/// The websocket URL clients use to reach a server at `addr`.
+pub fn url(addr: &SocketAddr) -> String {
+    format!("ws://{}/socket.io/?EIO=3&transport=websocket", addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use client::{self, Session};
+    use config::{Config, QueueMode, StrategyConfig};
+    use mapgen::Map;
+    use mock_server::{self, Lobby, MockGame, MockSettings, Player, Queue};
+    use state::State;
+    use std::collections::VecDeque;
+    use supervisor::Outcome;
+    use ws::{Handler, Sender, WebSocket};
+
+    struct Idle;
+
+    impl Handler for Idle {}
+
+    fn player(out: &Sender) -> Player {
+        Player {
+            out: out.clone(),
+            username: "".to_string(),
+            queue: None,
+            force_start: false,
+            game: None,
+        }
+    }
+
+    #[test]
+    fn test_capture_general() {
+        let map = Map::fixed(5, 5, 2);
+        let (ours, theirs) = (map.generals[0], map.generals[1]);
+        let mut start = map.to_game();
+        start.raw_map[ours] = 20;
+        start.raw_map[25 + theirs - 1] = 0;
+        start.raw_map[theirs - 1] = 10;
+        start.raw_map[theirs] = 3;
+        start.scores = vec![30, 3];
+        start.tiles = vec![2, 1];
+        let mut game = MockGame {
+            state: State::new(&start),
+            moves: vec![VecDeque::new(); 2],
+        };
+
+        game.queue_move(0, theirs - 1, theirs, false);
+        assert_eq!(game.step(), vec![(1, 0)]);
+        assert!(!game.state.is_alive(1));
+        assert_eq!(game.state.tile(3, 3).owner, 0);
+        assert_eq!(game.state.tile(3, 3).count, 6);
+    }
+
+    #[test]
+    fn test_ticker_captured() {
+        let mut lobby = Lobby::new(MockSettings {
+            width: 7,
+            height: 7,
+            ffa_players: 3,
+            ..MockSettings::default()
+        });
+        // Timers set on a socket that is not running are only queued, and the ones on a
+        // socket that is gone fail.
+        let socket = WebSocket::new(|_| Idle).unwrap();
+        let gone = WebSocket::new(|_| Idle).unwrap().broadcaster();
+        lobby.players.insert(1, player(&socket.broadcaster()));
+        lobby.players.insert(2, player(&gone));
+        lobby.players.insert(3, player(&socket.broadcaster()));
+        for id in 1..4 {
+            lobby.join(id, Queue::Ffa);
+        }
+        assert_eq!(lobby.games[&0].ticker, Some(1));
+
+        // The second player takes the general of the first, who times the game.
+        let mut map = Map::fixed(7, 7, 3);
+        map.terrain[9] = 1;
+        map.armies[9] = 20;
+        lobby.games.get_mut(&0).unwrap().engine.state = map.to_state();
+        lobby.attack(2, 9, 8, false);
+        assert!(lobby.tick(0));
+        assert_eq!(lobby.seat_of(1), None);
+        assert_eq!(lobby.games[&0].ticker, Some(3));
+
+        lobby.disconnect(1);
+        assert!(lobby.tick(0));
+        assert_eq!(lobby.games[&0].ticker, Some(3));
+        lobby.disconnect(3);
+        assert!(lobby.games.is_empty());
+    }
+
+    #[test]
+    fn test_full_game() {
+        let (addr, server) = mock_server::spawn(MockSettings {
+            width: 8,
+            height: 8,
+            turn_ms: 100,
+            max_turns: 24,
+            ffa_players: 2,
+        });
+
+        let config = Config {
+            server_url: mock_server::url(&addr),
+            user_id: "test".to_string(),
+            username: Some("[Bot] Test".to_string()),
+            queue: QueueMode::Custom,
+            room_id: "ci".to_string(),
+            max_games: Some(1),
+            strategy: StrategyConfig {
+                search_time_ms: 10,
+                ..StrategyConfig::default()
+            },
+        };
+        let mut session = Session::new(&config);
+        assert_eq!(client::run(&config, &mut session), Outcome::Finished);
+        assert_eq!(session.games_played, 1);
+
+        server.shutdown().unwrap();
+    }
+}
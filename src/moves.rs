@@ -0,0 +1,315 @@
+//! What the enemies did last turn. The server only sends the map as it is now, so the
+//! moves behind a change have to be worked out: every player moves at most once a turn,
+//! a move leaves `count - 1` or `count / 2` armies behind it, and armies grow at the end
+//! of the turn as the engine grows them. A move we see both ends of is checked against
+//! every tile it touched, while one going into or coming out of the fog is a best guess.
+//! Tiles our own last move touched changed for our doing, so they explain nothing.
+//!
+//! Players and tiles are numbered as on the server, like `Game`.
+
+use analysis::neighbours;
+use game::Game;
+
+const MOUNTAIN: i32 = -2;
+const FOG: i32 = -3;
+const FOG_OBSTACLE: i32 = -4;
+
+/// A move some player most likely made.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Move {
+    pub player: usize,
+    pub from: usize,
+    pub to: usize,
+    /// Armies that left the source.
+    pub armies: u32,
+    /// Whether we saw both ends of the move, so that it explains all they show.
+    pub seen: bool,
+}
+
+/// What `infer` needs of the previous update, kept instead of the whole game.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub turn: i32,
+    pub width: usize,
+    pub height: usize,
+    pub raw_map: Vec<i32>,
+    pub cities: Vec<usize>,
+    pub generals: Vec<isize>,
+    pub alive: Vec<bool>,
+    /// The source and target tile of the move we made since, if any.
+    pub our_move: Option<(usize, usize)>,
+}
+
+impl Snapshot {
+    pub fn new(game: &Game) -> Self {
+        Snapshot {
+            turn: game.turn,
+            width: game.width,
+            height: game.height,
+            raw_map: game.raw_map.clone(),
+            cities: game.cities.clone(),
+            generals: game.generals.clone(),
+            alive: game.alive.clone(),
+            our_move: game.our_move,
+        }
+    }
+
+    fn ours(&self, idx: usize) -> bool {
+        self.our_move.is_some_and(|(from, to)| idx == from || idx == to)
+    }
+}
+
+/// Whether tile `idx` of a raw map, armies then terrain, is in sight.
+fn is_visible(raw_map: &[i32], idx: usize) -> bool {
+    let terrain = raw_map[raw_map.len() / 2 + idx];
+    terrain != FOG && terrain != FOG_OBSTACLE
+}
+
+fn owner(raw_map: &[i32], idx: usize) -> Option<usize> {
+    let terrain = raw_map[raw_map.len() / 2 + idx];
+    if terrain >= 0 { Some(terrain as usize) } else { None }
+}
+
+/// Armies the tile at `idx` gains at the end of the turn `after` shows, if it has an
+/// owner by then.
+fn growth(before: &Snapshot, after: &Game, idx: usize) -> i32 {
+    if owner(&after.raw_map, idx).is_none() {
+        return 0;
+    }
+    let turn = after.turn;
+    let city = before.cities.contains(&idx) || after.cities.contains(&idx) ||
+               before.generals.contains(&(idx as isize));
+    let mut growth = 0;
+    if turn % 2 == 0 && city {
+        growth += 1;
+    }
+    if turn % 50 == 0 {
+        growth += 1;
+    }
+    growth
+}
+
+/// The most likely move of every enemy between `before` and `after`, the updates of two
+/// turns in a row. Updates further apart are too far apart to tell.
+pub fn infer(before: &Snapshot, after: &Game) -> Vec<Move> {
+    if before.width != after.width || before.height != after.height ||
+       before.alive.len() != after.alive.len() || after.turn != before.turn + 1 {
+        return vec![];
+    }
+    (0..after.alive.len())
+        .filter(|&player| player != after.player_index && before.alive[player])
+        .filter_map(|player| {
+            seen_move(before, after, player).or_else(|| arrival(before, after, player))
+        })
+        .collect()
+}
+
+/// The move of `player` from a tile we see, to the neighbour that best explains the
+/// change: one where the battle turned out as we see it, or else one in the fog.
+fn seen_move(before: &Snapshot, after: &Game, player: usize) -> Option<Move> {
+    let (width, height) = (after.width, after.height);
+    let size = width * height;
+    let (old, new) = (&before.raw_map, &after.raw_map);
+    let mut best: Option<Move> = None;
+
+    for from in 0..size {
+        if owner(old, from) != Some(player) || owner(new, from) != Some(player) ||
+           !is_visible(old, from) || before.ours(from) {
+            continue;
+        }
+        let count = old[from];
+        let armies = count - (new[from] - growth(before, after, from));
+        if count < 2 || (armies != count - 1 && armies != count / 2) {
+            continue;
+        }
+
+        let (x, y) = (from % width, from / width);
+        for (nx, ny, _) in neighbours(width, height, x, y) {
+            let to = ny * width + nx;
+            if old[size + to] == MOUNTAIN || before.ours(to) {
+                continue;
+            }
+            let candidate = Move {
+                player,
+                from,
+                to,
+                armies: armies as u32,
+                seen: is_visible(old, to) && is_visible(new, to),
+            };
+            if candidate.seen && explains(before, after, &candidate) {
+                return Some(candidate);
+            }
+            if !is_visible(new, to) && best.is_none() {
+                best = Some(candidate);
+            }
+        }
+    }
+    best
+}
+
+/// Whether the tile a move went to ended up as it shows in `after`.
+fn explains(before: &Snapshot, after: &Game, mv: &Move) -> bool {
+    let (defender, defending) = (owner(&before.raw_map, mv.to), before.raw_map[mv.to]);
+    let armies = mv.armies as i32;
+    let (owner_after, count) = if defender == Some(mv.player) {
+        (defender, defending + armies)
+    } else if armies > defending {
+        (Some(mv.player), armies - defending)
+    } else {
+        (defender, defending - armies)
+    };
+    owner(&after.raw_map, mv.to) == owner_after &&
+    after.raw_map[mv.to] - growth(before, after, mv.to) == count
+}
+
+/// The largest army of `player` we saw step out of the fog, from the fogged neighbour we
+/// last saw them hold if there is one.
+fn arrival(before: &Snapshot, after: &Game, player: usize) -> Option<Move> {
+    let (width, height) = (after.width, after.height);
+    let (old, new) = (&before.raw_map, &after.raw_map);
+    let mut best: Option<Move> = None;
+
+    for to in 0..width * height {
+        if owner(new, to) != Some(player) || !is_visible(old, to) || before.ours(to) {
+            continue;
+        }
+        let defending = old[to];
+        let landed = new[to] - growth(before, after, to);
+        let armies = if owner(old, to) == Some(player) {
+            landed - defending
+        } else {
+            landed + defending
+        };
+        if armies <= 0 || best.is_some_and(|best| best.armies >= armies as u32) {
+            continue;
+        }
+
+        let (x, y) = (to % width, to / width);
+        let fogged: Vec<usize> = neighbours(width, height, x, y)
+            .map(|(nx, ny, _)| ny * width + nx)
+            .filter(|&from| !is_visible(old, from) && !is_visible(new, from))
+            .collect();
+        let held = |&&from: &&usize| {
+            after.memory.get(from).is_some_and(|s| s.terrain == player as i32)
+        };
+        let from = fogged.iter().find(held).or_else(|| fogged.first());
+        if let Some(&from) = from {
+            best = Some(Move {
+                player,
+                from,
+                to,
+                armies: armies as u32,
+                seen: false,
+            });
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use arena::Observer;
+    use game::Game;
+    use mapgen::Map;
+    use moves::{self, Move, Snapshot};
+    use state::{Action, State};
+
+    /// Plays `action` for the enemy in `state` and returns what `infer` needs of our
+    /// update from before it.
+    fn turn(state: &mut State, observer: &mut Observer, game: &mut Game, action: Action)
+            -> Snapshot {
+        let before = Snapshot::new(game);
+        state.apply_actions(&[Action::none(), action]);
+        game.handle_game_update(&observer.update(state, 0)).unwrap();
+        before
+    }
+
+    /// 7x7 map with our general at (1, 1) and enemy armies of 9 at (2, 2), in sight,
+    /// and of 10 at (3, 1), in the fog.
+    fn setup() -> (State, Observer, Game) {
+        let mut map = Map::fixed(7, 7, 2);
+        map.terrain[2 * 7 + 2] = 1;
+        map.armies[2 * 7 + 2] = 9;
+        map.terrain[7 + 3] = 1;
+        map.armies[7 + 3] = 10;
+        let state = map.to_state();
+        let mut observer = Observer::new();
+        let mut game = Game::new();
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+        (state, observer, game)
+    }
+
+    #[test]
+    fn test_seen_moves() {
+        let (mut state, mut observer, mut game) = setup();
+        let before = turn(&mut state, &mut observer, &mut game, Action::new(false, 2, 2, 1));
+        assert_eq!(moves::infer(&before, &game),
+                   vec![Move {
+                            player: 1,
+                            from: 16,
+                            to: 9,
+                            armies: 8,
+                            seen: true,
+                        }]);
+
+        // Half the army on, into a turn where the general grows.
+        let before = turn(&mut state, &mut observer, &mut game, Action::new(true, 2, 1, 1));
+        assert_eq!(moves::infer(&before, &game),
+                   vec![Move {
+                            player: 1,
+                            from: 9,
+                            to: 2,
+                            armies: 4,
+                            seen: true,
+                        }]);
+
+        // Nothing happened, and updates too far apart tell nothing.
+        let before = turn(&mut state, &mut observer, &mut game, Action::none());
+        assert!(moves::infer(&before, &game).is_empty());
+        game.turn += 1;
+        assert!(moves::infer(&before, &game).is_empty());
+    }
+
+    #[test]
+    fn test_our_move() {
+        // Our 5 armies at (2, 1) attack the 8 the enemy holds at (2, 2), which looks just
+        // like the enemy moving half of them onto our tile.
+        let mut map = Map::fixed(7, 7, 2);
+        map.terrain[7 + 2] = 0;
+        map.armies[7 + 2] = 5;
+        map.terrain[2 * 7 + 2] = 1;
+        map.armies[2 * 7 + 2] = 8;
+        let mut state = map.to_state();
+        let mut observer = Observer::new();
+        let mut game = Game::new();
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+
+        game.record_attack(9, 16);
+        let before = Snapshot::new(&game);
+        state.apply_actions(&[Action::new(false, 2, 1, 3), Action::none()]);
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+        assert_eq!((game.raw_map[9], game.raw_map[16]), (1, 4));
+        assert!(moves::infer(&before, &game).is_empty());
+        let mut blind = before.clone();
+        blind.our_move = None;
+        assert_eq!(moves::infer(&blind, &game)[0].from, 16);
+        assert!(game.enemy_moves.is_empty());
+        assert_eq!(game.our_move, None);
+    }
+
+    #[test]
+    fn test_from_fog() {
+        let (mut state, mut observer, mut game) = setup();
+        let before = turn(&mut state, &mut observer, &mut game, Action::new(false, 3, 1, 0));
+        assert_eq!(moves::infer(&before, &game),
+                   vec![Move {
+                            player: 1,
+                            from: 10,
+                            to: 9,
+                            armies: 9,
+                            seen: false,
+                        }]);
+        // The game works it out with every update.
+        assert_eq!(game.enemy_moves, moves::infer(&before, &game));
+    }
+}
@@ -0,0 +1,176 @@
+//! What the obstacles in the fog are. The server shows fogged mountains and cities alike
+//! as obstacles, though a city can be taken and a mountain never can. Most obstacles we
+//! have seen before or know from the cities the server listed, and for the rest the odds
+//! come from how the map generator places cities and from how many of the obstacles we
+//! did see turned out to be cities.
+
+use analysis::manhattan;
+use game::Game;
+use mapgen::{MapSettings, CITY_CLEARANCE};
+
+const MOUNTAIN: i32 = -2;
+const FOG_OBSTACLE: i32 = -4;
+
+/// Obstacles we have seen count this many times as much as the generator's densities
+/// once there are this many of them.
+const PRIOR_WEIGHT: f64 = 20.0;
+
+/// What a fogged obstacle is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Obstacle {
+    Mountain,
+    City,
+    /// Not known either way, with the probability of it being a city.
+    Unknown(f64),
+}
+
+impl Obstacle {
+    /// The probability of the obstacle being a city.
+    pub fn city_probability(&self) -> f64 {
+        match *self {
+            Obstacle::Mountain => 0.0,
+            Obstacle::City => 1.0,
+            Obstacle::Unknown(p) => p,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObstacleMap {
+    width: usize,
+    height: usize,
+    /// Every tile the server has listed as a city this game.
+    cities: Vec<bool>,
+    /// What each tile that is an obstacle now is.
+    labels: Vec<Option<Obstacle>>,
+}
+
+impl ObstacleMap {
+    pub fn new() -> Self {
+        ObstacleMap::default()
+    }
+
+    /// Labels the obstacles of `game`, whose memory must already hold the latest update.
+    /// A map of another size belongs to another game, which starts over.
+    pub fn update(&mut self, game: &Game) {
+        let size = game.width * game.height;
+        if game.width != self.width || game.height != self.height {
+            self.width = game.width;
+            self.height = game.height;
+            self.cities = vec![false; size];
+        }
+        for &city in &game.cities {
+            self.cities[city] = true;
+        }
+
+        let mountains = game.memory.sightings().filter(|&(_, s)| s.terrain == MOUNTAIN).count();
+        let cities = (0..size)
+            .filter(|&idx| self.cities[idx] || game.memory.get(idx).is_some_and(|s| s.city))
+            .count();
+        let settings = MapSettings::default();
+        let prior = settings.city_density / (settings.city_density + settings.mountain_density);
+        let odds = (cities as f64 + PRIOR_WEIGHT * prior) /
+                   ((cities + mountains) as f64 + PRIOR_WEIGHT);
+
+        let generals: Vec<(usize, usize)> = game.memory
+            .sightings()
+            .filter(|&(_, s)| s.general)
+            .map(|(idx, _)| (idx % self.width, idx / self.width))
+            .collect();
+
+        self.labels = (0..size)
+            .map(|idx| {
+                if game.raw_map[size + idx] != FOG_OBSTACLE {
+                    return None;
+                }
+                let tile = (idx % self.width, idx / self.width);
+                Some(match game.memory.get(idx) {
+                    Some(s) if s.terrain == MOUNTAIN => Obstacle::Mountain,
+                    _ if self.cities[idx] => Obstacle::City,
+                    Some(s) if s.city => Obstacle::City,
+                    _ if generals.iter().any(|&g| manhattan(g, tile) <= CITY_CLEARANCE) => {
+                        Obstacle::Unknown(0.0)
+                    }
+                    _ => Obstacle::Unknown(odds),
+                })
+            })
+            .collect();
+    }
+
+    /// What the tile at `idx` is, if it is an obstacle.
+    pub fn classify(&self, idx: usize) -> Option<Obstacle> {
+        self.labels.get(idx).cloned().unwrap_or(None)
+    }
+
+    /// Every obstacle that may be a city, with its probability, the likeliest first.
+    pub fn likely_cities(&self) -> Vec<(usize, f64)> {
+        let mut cities: Vec<(usize, f64)> = self.labels
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, label)| label.map(|l| (idx, l.city_probability())))
+            .filter(|&(_, p)| p > 0.0)
+            .collect();
+        cities.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        cities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arena::Observer;
+    use game::Game;
+    use mapgen::Map;
+    use obstacles::{Obstacle, ObstacleMap};
+    use state::{State, TileType};
+
+    /// 7x7 map with our general at (1, 1), mountains at (5, 3) and (3, 6) and a city at
+    /// (3, 3).
+    fn map() -> Map {
+        Map::fixed(7, 7, 2)
+    }
+
+    #[test]
+    fn test_classify() {
+        let (mut map, mut game, mut observer) = (map(), Game::new(), Observer::new());
+        map.terrain[7 + 3] = -2;
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+        let unknown = game.obstacles.classify(26);
+        assert!(match unknown {
+            Some(Obstacle::Unknown(p)) => p > 0.0 && p < 0.5,
+            _ => false,
+        });
+        // No city stands this close to a general.
+        assert_eq!(game.obstacles.classify(10), Some(Obstacle::Unknown(0.0)));
+        assert_eq!(game.obstacles.classify(12), None);
+
+        // Seen once, known for good.
+        map.terrain[2 * 7 + 4] = 0;
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+        assert_eq!(game.obstacles.classify(26), None);
+        map.terrain[2 * 7 + 4] = -1;
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+        assert_eq!(game.obstacles.classify(26), Some(Obstacle::Mountain));
+        assert_eq!(game.obstacles.classify(24), Some(Obstacle::City));
+        assert_eq!(game.obstacles.likely_cities()[0], (24, 1.0));
+    }
+
+    #[test]
+    fn test_city_history() {
+        let mut game = Game::new();
+        game.handle_game_update(&Observer::new().update(&map().to_state(), 0)).unwrap();
+        let before = game.obstacles.classify(26).unwrap().city_probability();
+
+        // A city the server lists in the fog, which also makes cities seem more common.
+        game.cities.push(6 * 7 + 3);
+        let mut obstacles = ObstacleMap::new();
+        obstacles.update(&game);
+        assert_eq!(obstacles.classify(6 * 7 + 3), Some(Obstacle::City));
+        let after = obstacles.classify(26).unwrap().city_probability();
+        assert!(after > before, "{} <= {}", after, before);
+
+        // The engine takes it for a city with the smallest garrison there is.
+        game.obstacles = obstacles;
+        let tile = State::new(&game).tile(3, 6).clone();
+        assert_eq!((tile.kind, tile.count), (TileType::City, 40));
+    }
+}
@@ -0,0 +1,290 @@
+//! The opening: the first turns before all land grows, when nobody is in reach yet and
+//! the only thing that matters is how much land we hold when it does.
+//!
+//! The plan is a sequence of launches. The general waits until it holds a launch's
+//! worth of armies, then a single army sets out from it and claims a new tile every turn
+//! until it has one left, and the general waits for the next launch. Every pair of first
+//! and later launch sizes is played out on the engine, which knows the general gains an
+//! army every second turn, and the schedule holding the most land at the end wins.
+//! That is done once, as time allows; when the game strays from the plan, only the
+//! chosen launch sizes are played out again from where it stands.
+
+use analysis::{self, neighbours};
+use game::Game;
+use state::{Action, State, TileType};
+use strategy::Strategy;
+use time::precise_time_ns;
+
+/// Launch sizes tried for the first launch and for every one after it.
+const MIN_LAUNCH: u32 = 2;
+const MAX_LAUNCH: u32 = 14;
+
+/// A move for every turn from `start` on, `Action::none` for the turns spent waiting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpeningPlan {
+    pub start: usize,
+    /// The size of the first launch and of every one after it.
+    pub first: u32,
+    pub rest: u32,
+    pub moves: Vec<Action>,
+    /// Land held once the moves are made.
+    pub land: u32,
+}
+
+impl OpeningPlan {
+    /// The move planned for `turn`, if the plan covers it.
+    pub fn move_for(&self, turn: usize) -> Option<Action> {
+        turn.checked_sub(self.start).and_then(|i| self.moves.get(i)).cloned()
+    }
+
+    /// Whether an army set out before `turn`.
+    fn launched_before(&self, turn: usize) -> bool {
+        let played = turn.saturating_sub(self.start).min(self.moves.len());
+        self.moves[..played].iter().any(|action| !action.is_empty())
+    }
+}
+
+/// Where our general stands, if we see it.
+fn our_general(state: &State) -> Option<(usize, usize)> {
+    state.owned_tiles(0)
+        .iter()
+        .find(|tile| state.tile(tile.x, tile.y).kind == TileType::General)
+        .map(|tile| (tile.x, tile.y))
+}
+
+/// Whether an army can claim the tile for nothing: empty land nobody holds.
+fn is_free(state: &State, x: usize, y: usize) -> bool {
+    let tile = state.tile(x, y);
+    tile.owner == state.player_count() && tile.count == 0 &&
+    (tile.kind == TileType::Plain || tile.kind == TileType::Fog)
+}
+
+/// The move taking the army on `(x, y)` further out: onto the free neighbour with the
+/// most free tiles around it, or else towards the nearest free tile.
+fn expand_step(state: &State, x: usize, y: usize) -> Option<Action> {
+    if state.tile(x, y).owner != 0 || state.tile(x, y).count <= 1 {
+        return None;
+    }
+    let (width, height) = (state.width(), state.height());
+    let free_around = |x: usize, y: usize| {
+        neighbours(width, height, x, y).filter(|&(nx, ny, _)| is_free(state, nx, ny)).count()
+    };
+
+    let best = neighbours(width, height, x, y)
+        .filter(|&(nx, ny, _)| is_free(state, nx, ny))
+        .max_by_key(|&(nx, ny, _)| free_around(nx, ny));
+    if let Some((_, _, k)) = best {
+        return Some(Action::new(false, x, y, k));
+    }
+
+    let mut free = vec![];
+    for ny in 0..height {
+        for nx in 0..width {
+            if is_free(state, nx, ny) {
+                free.push((nx, ny));
+            }
+        }
+    }
+    analysis::bfs(state, 0, &free).step(x, y).map(|k| Action::new(false, x, y, k))
+}
+
+/// Plays launches of `first` armies, then of `rest`, until turn `end`.
+fn simulate(state: &State, general: (usize, usize), first: u32, rest: u32, end: usize) -> OpeningPlan {
+    let mut state = state.clone();
+    let mut plan = OpeningPlan {
+        start: state.turn(),
+        first,
+        rest,
+        moves: vec![],
+        land: 0,
+    };
+    let mut army: Option<(usize, usize)> = None;
+    let mut launches = 0;
+
+    while state.turn() < end {
+        if army.is_none() {
+            let size = if launches == 0 { first } else { rest };
+            if state.tile(general.0, general.1).count >= size {
+                army = Some(general);
+                launches += 1;
+            }
+        }
+
+        let action = army.and_then(|(x, y)| expand_step(&state, x, y));
+        match action {
+            Some(action) => army = Some((action.dst_x(), action.dst_y())),
+            None => army = None,
+        }
+        let action = action.unwrap_or_else(Action::none);
+        state.apply_action(action);
+        plan.moves.push(action);
+    }
+
+    plan.land = state.land(0);
+    plan
+}
+
+/// The schedule holding the most land at turn `end`, from our general in `state`, out
+/// of those played out by `deadline`, at least one. Without a general in sight there is
+/// nothing to plan.
+pub fn plan(state: &State, end: usize, deadline: u64) -> Option<OpeningPlan> {
+    let general = our_general(state)?;
+
+    let mut best: Option<OpeningPlan> = None;
+    for first in MIN_LAUNCH..MAX_LAUNCH + 1 {
+        for rest in MIN_LAUNCH..MAX_LAUNCH + 1 {
+            let candidate = simulate(state, general, first, rest, end);
+            if best.as_ref().is_none_or(|best| candidate.land > best.land) {
+                best = Some(candidate);
+            }
+            if precise_time_ns() >= deadline {
+                return best;
+            }
+        }
+    }
+    best
+}
+
+/// `plan` played out again from `state` with the same launch sizes, for when the game
+/// has left it behind.
+fn replan(plan: &OpeningPlan, state: &State, end: usize) -> Option<OpeningPlan> {
+    let general = our_general(state)?;
+    let first = if plan.launched_before(state.turn()) { plan.rest } else { plan.first };
+    Some(simulate(state, general, first, plan.rest, end))
+}
+
+/// Plays the opening plan until turn `turns`, then hands the game over to `inner`.
+pub struct Opening {
+    turns: usize,
+    plan: Option<OpeningPlan>,
+    inner: Box<dyn Strategy>,
+}
+
+impl Opening {
+    pub fn new(turns: usize, inner: Box<dyn Strategy>) -> Self {
+        Opening {
+            turns,
+            plan: None,
+            inner,
+        }
+    }
+
+    /// The planned move for the current turn. The first call plans by `deadline`, and
+    /// later ones only play the plan out again when the game has left it behind.
+    fn planned_move(&mut self, game: &Game, deadline: u64) -> Action {
+        let state = State::new(game);
+        let turn = game.turn as usize;
+        let planned = self.plan.as_ref().and_then(|plan| plan.move_for(turn));
+        if let Some(action) = planned {
+            if action.is_empty() || state.clone().apply_move(0, action) {
+                return action;
+            }
+        }
+
+        self.plan = match self.plan {
+            Some(ref planned) => replan(planned, &state, self.turns),
+            None => plan(&state, self.turns, deadline),
+        };
+        self.plan.as_ref().and_then(|plan| plan.move_for(turn)).unwrap_or_else(Action::none)
+    }
+}
+
+impl Strategy for Opening {
+    fn game_start(&mut self, game: &Game) {
+        self.plan = None;
+        self.inner.game_start(game);
+    }
+
+    fn observe(&mut self, game: &Game) {
+        self.inner.observe(game);
+    }
+
+    fn next_move(&mut self, game: &Game, deadline: u64) -> Action {
+        if (game.turn as usize) < self.turns {
+            self.planned_move(game, deadline)
+        } else {
+            self.inner.next_move(game, deadline)
+        }
+    }
+
+    fn game_end(&mut self, won: bool) {
+        self.inner.game_end(won);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mapgen::{Map, MapSettings};
+    use opening::{self, Opening};
+    use state::Action;
+    use strategy::{RandomBot, Strategy};
+
+    #[test]
+    fn test_plan() {
+        let map = Map::generate(&MapSettings {
+                                    players: 2,
+                                    size: Some((18, 18)),
+                                    ..MapSettings::default()
+                                },
+                                7);
+        let mut state = map.to_state();
+        let plan = opening::plan(&state, 50, u64::MAX).unwrap();
+        assert_eq!((plan.start, plan.moves.len()), (0, 50));
+        // A tile a turn at best, once the general has an army to spare.
+        assert!(plan.land > 20 && plan.land <= 50);
+
+        // The moves hold up on the engine.
+        for &action in &plan.moves {
+            let mut actions = vec![Action::none(); 2];
+            actions[0] = action;
+            state.apply_actions(&actions);
+        }
+        assert_eq!(state.land(0), plan.land);
+    }
+
+    #[test]
+    fn test_hand_off() {
+        let map = Map::fixed(8, 8, 2);
+        let mut game = map.to_game();
+        let mut bot = Opening::new(10, Box::new(RandomBot::new()));
+        bot.game_start(&game);
+        game.turn = 2;
+        let first = bot.next_move(&game, 0);
+        assert_eq!(bot.plan.as_ref().map(|plan| plan.start), Some(2));
+        assert_eq!(Some(first), bot.plan.as_ref().unwrap().move_for(2));
+
+        // Past the opening the plan is left alone.
+        game.turn = 10;
+        bot.next_move(&game, 0);
+        assert_eq!(bot.plan.as_ref().map(|plan| plan.start), Some(2));
+    }
+
+    #[test]
+    fn test_replan() {
+        let map = Map::fixed(8, 8, 2);
+        // Out of time, the first schedule tried is the plan.
+        let plan = opening::plan(&map.to_state(), 20, 0).unwrap();
+        assert_eq!((plan.first, plan.rest), (2, 2));
+
+        let mut game = map.to_game();
+        let mut bot = Opening::new(20, Box::new(RandomBot::new()));
+        bot.game_start(&game);
+        bot.next_move(&game, u64::MAX);
+        let planned = bot.plan.clone().unwrap();
+        assert_eq!(planned.start, 0);
+
+        // The board never moves on, so the first army out of the general is not where
+        // the plan has it, and the same launch sizes are played out from there.
+        let turn = (1..20)
+            .find(|&turn| {
+                let action = planned.move_for(turn).unwrap();
+                !action.is_empty() && (action.src_x(), action.src_y()) != (1, 1)
+            })
+            .unwrap();
+        game.turn = turn as i32;
+        bot.next_move(&game, 0);
+        let replanned = bot.plan.as_ref().unwrap();
+        assert_eq!(replanned.start, turn);
+        assert_eq!((replanned.first, replanned.rest), (planned.rest, planned.rest));
+    }
+}
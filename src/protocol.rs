@@ -0,0 +1,241 @@
+use json;
+use json::JsonValue;
+use std::fmt;
+
+/// Fallback keepalive settings, used until the server's open packet tells us otherwise.
+pub const DEFAULT_PING_INTERVAL_MS: u64 = 25_000;
+pub const DEFAULT_PING_TIMEOUT_MS: u64 = 60_000;
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    Empty,
+    UnknownPacketType(char),
+    UnknownSocketType(char),
+    BadJson(String),
+    BadOpen(String),
+    BadEvent(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::Empty => write!(f, "empty packet"),
+            ProtocolError::UnknownPacketType(c) => write!(f, "unknown engine.io packet type {:?}", c),
+            ProtocolError::UnknownSocketType(c) => write!(f, "unknown socket.io packet type {:?}", c),
+            ProtocolError::BadJson(ref e) => write!(f, "malformed packet data: {}", e),
+            ProtocolError::BadOpen(ref e) => write!(f, "malformed open packet: {}", e),
+            ProtocolError::BadEvent(ref e) => write!(f, "malformed event: {}", e),
+        }
+    }
+}
+
+/// Parameters of the Engine.IO session, sent by the server in the open packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenInfo {
+    pub sid: String,
+    pub upgrades: Vec<String>,
+    pub ping_interval: u64,
+    pub ping_timeout: u64,
+}
+
+/// Engine.IO (protocol revision 3) transport level packets.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    Open(OpenInfo),
+    Close,
+    Ping(String),
+    Pong(String),
+    Message(SocketPacket),
+    Upgrade,
+    Noop,
+}
+
+/// Socket.IO packets carried inside Engine.IO messages.
+#[derive(Debug, PartialEq)]
+pub enum SocketPacket {
+    Connect(String),
+    Disconnect(String),
+    Event {
+        namespace: String,
+        id: Option<u64>,
+        name: String,
+        args: Vec<JsonValue>,
+    },
+    Ack {
+        namespace: String,
+        id: Option<u64>,
+        args: Vec<JsonValue>,
+    },
+    Error(String, JsonValue),
+}
+
+pub fn decode(text: &str) -> Result<Packet, ProtocolError> {
+    let mut chars = text.chars();
+    let kind = chars.next().ok_or(ProtocolError::Empty)?;
+    let body = chars.as_str();
+
+    match kind {
+        '0' => decode_open(body).map(Packet::Open),
+        '1' => Ok(Packet::Close),
+        '2' => Ok(Packet::Ping(body.to_string())),
+        '3' => Ok(Packet::Pong(body.to_string())),
+        '4' => decode_socket(body).map(Packet::Message),
+        '5' => Ok(Packet::Upgrade),
+        '6' => Ok(Packet::Noop),
+        _ => Err(ProtocolError::UnknownPacketType(kind)),
+    }
+}
+
+fn decode_open(body: &str) -> Result<OpenInfo, ProtocolError> {
+    let data = json::parse(body).map_err(|e| ProtocolError::BadOpen(e.to_string()))?;
+    let sid = data["sid"].as_str().ok_or_else(|| ProtocolError::BadOpen("missing sid".to_string()))?;
+
+    Ok(OpenInfo {
+        sid: sid.to_string(),
+        upgrades: data["upgrades"]
+            .members()
+            .filter_map(|upgrade| upgrade.as_str())
+            .map(|upgrade| upgrade.to_string())
+            .collect(),
+        ping_interval: data["pingInterval"].as_u64().unwrap_or(DEFAULT_PING_INTERVAL_MS),
+        ping_timeout: data["pingTimeout"].as_u64().unwrap_or(DEFAULT_PING_TIMEOUT_MS),
+    })
+}
+
+/// Decodes `<type>[/namespace,][ack id][json data]`.
+fn decode_socket(body: &str) -> Result<SocketPacket, ProtocolError> {
+    let mut chars = body.chars();
+    let kind = chars.next().ok_or(ProtocolError::Empty)?;
+    let mut rest = chars.as_str();
+
+    let mut namespace = "/".to_string();
+    if rest.starts_with('/') {
+        let end = rest.find(',').unwrap_or(rest.len());
+        namespace = rest[..end].to_string();
+        rest = if end < rest.len() { &rest[end + 1..] } else { "" };
+    }
+
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let id = if digits > 0 {
+        rest[..digits].parse().ok()
+    } else {
+        None
+    };
+    rest = &rest[digits..];
+
+    let data = if rest.is_empty() {
+        JsonValue::Null
+    } else {
+        json::parse(rest).map_err(|e| ProtocolError::BadJson(e.to_string()))?
+    };
+
+    match kind {
+        '0' => Ok(SocketPacket::Connect(namespace)),
+        '1' => Ok(SocketPacket::Disconnect(namespace)),
+        '2' | '5' => {
+            let mut args: Vec<JsonValue> = match data {
+                JsonValue::Array(args) => args,
+                _ => return Err(ProtocolError::BadEvent("event data is not an array".to_string())),
+            };
+            if args.is_empty() {
+                return Err(ProtocolError::BadEvent("event has no name".to_string()));
+            }
+            let name = match args.remove(0).as_str() {
+                Some(name) => name.to_string(),
+                None => return Err(ProtocolError::BadEvent("event name is not a string".to_string())),
+            };
+            Ok(SocketPacket::Event {
+                namespace,
+                id,
+                name,
+                args,
+            })
+        }
+        '3' | '6' => {
+            let args = match data {
+                JsonValue::Array(args) => args,
+                JsonValue::Null => vec![],
+                other => vec![other],
+            };
+            Ok(SocketPacket::Ack {
+                namespace,
+                id,
+                args,
+            })
+        }
+        '4' => Ok(SocketPacket::Error(namespace, data)),
+        _ => Err(ProtocolError::UnknownSocketType(kind)),
+    }
+}
+
+pub fn encode_ping() -> String {
+    "2".to_string()
+}
+
+pub fn encode_pong(data: &str) -> String {
+    format!("3{}", data)
+}
+
+/// Encodes a Socket.IO event on the default namespace, `data` being `[name, args...]`.
+pub fn encode_event(data: &JsonValue) -> String {
+    format!("42{}", data)
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{decode, encode_event, Packet, SocketPacket, ProtocolError};
+
+    #[test]
+    fn test_decode_open() {
+        let packet = decode("0{\"sid\":\"abc\",\"upgrades\":[],\"pingInterval\":25000,\
+                             \"pingTimeout\":60000}")
+            .unwrap();
+        match packet {
+            Packet::Open(info) => {
+                assert_eq!(info.sid, "abc");
+                assert_eq!(info.ping_interval, 25_000);
+                assert_eq!(info.ping_timeout, 60_000);
+            }
+            _ => panic!("Expected open packet, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_decode_transport() {
+        assert_eq!(decode("1").unwrap(), Packet::Close);
+        assert_eq!(decode("2probe").unwrap(), Packet::Ping("probe".to_string()));
+        assert_eq!(decode("3").unwrap(), Packet::Pong("".to_string()));
+        assert_eq!(decode("5").unwrap(), Packet::Upgrade);
+        assert_eq!(decode("6").unwrap(), Packet::Noop);
+        assert_eq!(decode("9"), Err(ProtocolError::UnknownPacketType('9')));
+        assert_eq!(decode(""), Err(ProtocolError::Empty));
+    }
+
+    #[test]
+    fn test_decode_socket() {
+        assert_eq!(decode("40").unwrap(),
+                   Packet::Message(SocketPacket::Connect("/".to_string())));
+        assert_eq!(decode("41/chat,").unwrap(),
+                   Packet::Message(SocketPacket::Disconnect("/chat".to_string())));
+
+        match decode("4212[\"game_won\",{\"turn\":4}]").unwrap() {
+            Packet::Message(SocketPacket::Event { namespace, id, name, args }) => {
+                assert_eq!(namespace, "/");
+                assert_eq!(id, Some(12));
+                assert_eq!(name, "game_won");
+                assert_eq!(args.len(), 1);
+                assert_eq!(args[0]["turn"].as_u32(), Some(4));
+            }
+            other => panic!("Expected event, got {:?}", other),
+        }
+
+        assert!(decode("42{\"a\":1}").is_err());
+        assert!(decode("42[1]").is_err());
+    }
+
+    #[test]
+    fn test_encode_event() {
+        assert_eq!(encode_event(&array!["attack", 1, 2, false]),
+                   "42[\"attack\",1,2,false]");
+    }
+}
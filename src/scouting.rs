@@ -0,0 +1,253 @@
+//! Where the enemy generals are. The server only shows a general once it is in sight, so
+//! until then every tile gets a probability of holding it, from what we know:
+//!
+//! - A general never moves, so no tile we have seen without one can hold it, nor can a
+//!   mountain or a city.
+//! - Generals start apart from each other, as far as the map generator allows.
+//! - Land grows out from the general, so it sits amid the land we have seen it hold.
+//! - An army stepping out of the fog comes from the general's side of it more often than
+//!   not.
+//!
+//! Tiles and players are numbered as on the server, like `Game`.
+
+use analysis::{manhattan, neighbours};
+use game::Game;
+use mapgen;
+
+const FOG: i32 = -3;
+
+/// Weight kept by tiles closer to a known general than generals start apart. The map
+/// generator only puts them there when a crowded map leaves no room.
+const CROWDED: f64 = 0.05;
+/// Steps of average distance from the land a player holds that halve the weight of a
+/// tile.
+const LAND_HALVING: f64 = 4.0;
+/// Weight kept by tiles on the near side of an army that came out of the fog.
+const WRONG_SIDE: f64 = 0.6;
+/// Armies coming out of the fog remembered for each player.
+const MAX_ARRIVALS: usize = 16;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeneralFinder {
+    width: usize,
+    height: usize,
+    /// Terrain at the last update, to tell armies coming out of the fog.
+    last: Vec<i32>,
+    /// For every player, the latest tiles their armies stepped onto out of the fog, each
+    /// with the fogged tile they came from.
+    arrivals: Vec<Vec<(usize, usize)>>,
+    /// For every player, the probability of each tile holding their general. Ours is
+    /// empty.
+    maps: Vec<Vec<f64>>,
+}
+
+impl GeneralFinder {
+    pub fn new() -> Self {
+        GeneralFinder::default()
+    }
+
+    /// Takes in the latest update of `game`, whose memory must already hold it. A map of
+    /// another size belongs to another game, which starts over.
+    pub fn update(&mut self, game: &Game) {
+        let players = game.alive.len();
+        if game.width != self.width || game.height != self.height || self.maps.len() != players {
+            *self = GeneralFinder {
+                width: game.width,
+                height: game.height,
+                last: vec![],
+                arrivals: vec![vec![]; players],
+                maps: vec![vec![]; players],
+            };
+        }
+
+        self.record_arrivals(game);
+        for player in 0..players {
+            self.maps[player] = if player == game.player_index {
+                vec![]
+            } else {
+                self.estimate(game, player)
+            };
+        }
+        self.last = game.raw_map[self.width * self.height..].to_vec();
+    }
+
+    /// The probability of `player`'s general standing on the tile at `idx`.
+    pub fn probability(&self, player: usize, idx: usize) -> f64 {
+        self.maps.get(player).and_then(|map| map.get(idx)).cloned().unwrap_or(0.0)
+    }
+
+    /// Up to `count` of the tiles most likely to hold `player`'s general, most likely
+    /// first, with their probabilities.
+    pub fn most_likely(&self, player: usize, count: usize) -> Vec<(usize, f64)> {
+        let mut tiles: Vec<(usize, f64)> = match self.maps.get(player) {
+            Some(map) => map.iter().cloned().enumerate().filter(|&(_, p)| p > 0.0).collect(),
+            None => return vec![],
+        };
+        tiles.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        tiles.truncate(count);
+        tiles
+    }
+
+    #[inline]
+    fn coords(&self, idx: usize) -> (usize, usize) {
+        (idx % self.width, idx / self.width)
+    }
+
+    /// Notes every enemy army that stepped out of the fog onto a tile we already saw.
+    fn record_arrivals(&mut self, game: &Game) {
+        if self.last.is_empty() {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        let size = width * height;
+        for idx in 0..size {
+            let owner = game.raw_map[size + idx];
+            if owner < 0 || owner as usize == game.player_index || self.last[idx] == FOG ||
+               self.last[idx] == owner {
+                continue;
+            }
+            let (x, y) = self.coords(idx);
+            for (nx, ny, _) in neighbours(width, height, x, y) {
+                let from = ny * width + nx;
+                if game.raw_map[size + from] == FOG {
+                    let arrivals = &mut self.arrivals[owner as usize];
+                    arrivals.push((idx, from));
+                    if arrivals.len() > MAX_ARRIVALS {
+                        arrivals.remove(0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn estimate(&self, game: &Game, player: usize) -> Vec<f64> {
+        let size = self.width * self.height;
+        let mut weights = vec![0.0; size];
+
+        // A general in sight, or one we have seen before, is where it is.
+        let seen = game.generals
+            .get(player)
+            .and_then(|&idx| if idx >= 0 { Some(idx as usize) } else { None })
+            .or_else(|| {
+                game.memory
+                    .sightings()
+                    .find(|&(_, s)| s.general && s.terrain == player as i32)
+                    .map(|(idx, _)| idx)
+            });
+        if let Some(idx) = seen {
+            weights[idx] = 1.0;
+            return weights;
+        }
+
+        let spacing = mapgen::general_spacing(self.width, self.height, game.alive.len());
+        let generals: Vec<(usize, usize)> = game.memory
+            .sightings()
+            .filter(|&(_, s)| s.general)
+            .map(|(idx, _)| self.coords(idx))
+            .collect();
+        let land: Vec<(usize, usize)> = game.memory
+            .sightings()
+            .filter(|&(_, s)| s.terrain == player as i32)
+            .map(|(idx, _)| self.coords(idx))
+            .collect();
+        let arrivals: Vec<((usize, usize), (usize, usize))> = self.arrivals[player]
+            .iter()
+            .map(|&(onto, from)| (self.coords(onto), self.coords(from)))
+            .collect();
+
+        for (idx, weight) in weights.iter_mut().enumerate() {
+            // Obstacles in the fog are mountains or cities, and the rest we have seen.
+            if game.raw_map[size + idx] != FOG || game.memory.get(idx).is_some() {
+                continue;
+            }
+            let tile = self.coords(idx);
+            *weight = 1.0;
+            if generals.iter().any(|&general| manhattan(general, tile) < spacing) {
+                *weight *= CROWDED;
+            }
+            if !land.is_empty() {
+                let total: usize = land.iter().map(|&t| manhattan(t, tile)).sum();
+                *weight *= 0.5f64.powf(total as f64 / land.len() as f64 / LAND_HALVING);
+            }
+            for &(onto, from) in &arrivals {
+                if manhattan(from, tile) >= manhattan(onto, tile) {
+                    *weight *= WRONG_SIDE;
+                }
+            }
+        }
+
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            for weight in &mut weights {
+                *weight /= total;
+            }
+        }
+        weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arena::Observer;
+    use game::Game;
+    use mapgen::Map;
+
+    /// 11x11 map with our general at (1, 1) and the enemy's at (9, 9).
+    fn map() -> Map {
+        Map::fixed(11, 11, 2)
+    }
+
+    fn show(game: &mut Game, observer: &mut Observer, map: &Map) {
+        game.handle_game_update(&observer.update(&map.to_state(), 0)).unwrap();
+    }
+
+    #[test]
+    fn test_spawn() {
+        let mut game = Game::new();
+        show(&mut game, &mut Observer::new(), &map());
+        let finder = &game.enemy_generals;
+        // Nothing in sight can hold it, and tiles too close to us hardly can.
+        assert_eq!(finder.probability(1, 12), 0.0);
+        assert_eq!(finder.probability(0, 12), 0.0);
+        assert!(finder.probability(1, 5 * 11 + 4) < finder.probability(1, 9 * 11 + 9) / 10.0);
+        let total: f64 = (0..121).map(|idx| finder.probability(1, idx)).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        let likely = finder.most_likely(1, 3);
+        assert_eq!(likely.len(), 3);
+        assert!(likely[0].1 >= likely[1].1 && likely[1].1 >= likely[2].1);
+    }
+
+    #[test]
+    fn test_evidence() {
+        let (mut map, mut game, mut observer) = (map(), Game::new(), Observer::new());
+        let (left, right) = (9 * 11 + 1, 11 + 9);
+
+        // Enemy land comes into view below us, so its general leans that way.
+        map.terrain[2 * 11 + 1] = 0;
+        for x in 0..3 {
+            map.terrain[3 * 11 + x] = 1;
+        }
+        show(&mut game, &mut observer, &map);
+        let before = game.enemy_generals.probability(1, left) /
+                     game.enemy_generals.probability(1, right);
+        assert!(before > 1.0);
+
+        // An army steps out of the fog on the right.
+        map.terrain[2 * 11 + 2] = 1;
+        map.armies[2 * 11 + 2] = 3;
+        show(&mut game, &mut observer, &map);
+        let after = game.enemy_generals.probability(1, left) /
+                    game.enemy_generals.probability(1, right);
+        assert!(after < before);
+
+        // Once seen, the general stays found after it goes back into the fog.
+        map.terrain[8 * 11 + 8] = 0;
+        show(&mut game, &mut observer, &map);
+        assert_eq!(game.enemy_generals.most_likely(1, 2), vec![(9 * 11 + 9, 1.0)]);
+        map.terrain[8 * 11 + 8] = -1;
+        show(&mut game, &mut observer, &map);
+        assert_eq!(game.generals[1], -1);
+        assert_eq!(game.enemy_generals.most_likely(1, 2), vec![(9 * 11 + 9, 1.0)]);
+    }
+}
@@ -1,58 +1,85 @@
+use std::sync::Arc;
+
+use analysis::DistanceMap;
 use game::Game;
+use mapgen::MIN_GARRISON;
+use obstacles::Obstacle;
 use rand::Rng;
 
-struct Action(pub u16);
+pub const DX: [isize; 4] = [-1, 0, 1, 0];
+pub const DY: [isize; 4] = [0, -1, 0, 1];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pair {
+    pub x: usize,
+    pub y: usize,
+}
 
 /// Move representation:
 /// Bit 13: Whether anything is being moved or not. None is a valid move.
 /// Bit 12: Whether the move is "half" the units or not.
 /// Bits 10-11: Direction of the move, as LEFT = 0, UP = 1, RIGHT = 2, DOWN = 3
-/// Bits 0-5: Source x co-ord.
-/// Bits 6-10: Source y co-ord.
+/// Bits 0-4: Source x co-ord.
+/// Bits 5-9: Source y co-ord.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Action(pub u16);
+
 macro_rules! make_action {
     ($half:expr, $srcx:expr, $srcy:expr, $dir:expr) => (
-        if half {
-            0x2000 + 0x1000 + ((dir & 0x0007) << 10) + ((srcy & 0x0000001F) << 5) + ((srcx & 0x0000001F) << 0)
-        } else {
-            0x2000 + ((dir & 0x00000007) << 10) + ((srcy & 0x0000001F) << 5) + ((srcx & 0x0000001F) << 0)
-        } as Action
+        Action(0x2000 + if $half { 0x1000 } else { 0 } + ((($dir as u16) & 0x0003) << 10) +
+               ((($srcy as u16) & 0x001F) << 5) + (($srcx as u16) & 0x001F))
     )
 }
 
 impl Action {
     #[inline]
-    fn is_empty(&self) -> bool {
-        self & 0x2000
+    pub fn none() -> Action {
+        Action(0)
+    }
+
+    #[inline]
+    pub fn new(half: bool, src_x: usize, src_y: usize, dir: usize) -> Action {
+        make_action!(half, src_x, src_y, dir)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 & 0x2000 == 0
     }
 
     #[inline]
-    fn is_half(&self) -> bool {
-        self & 0x1000
+    pub fn is_half(&self) -> bool {
+        self.0 & 0x1000 != 0
     }
 
     #[inline]
-    fn src_x(&self) -> usize {
-        self & 0x001F
+    pub fn dir(&self) -> usize {
+        ((self.0 >> 10) & 0x0003) as usize
     }
 
     #[inline]
-    fn dst_x(&self) -> usize {
-        self & 0x001F + DX[(self >> 10) & 0x0007]
+    pub fn src_x(&self) -> usize {
+        (self.0 & 0x001F) as usize
     }
 
     #[inline]
-    fn src_y(&self) -> usize {
-        self & 0x03E0
+    pub fn dst_x(&self) -> usize {
+        (self.src_x() as isize + DX[self.dir()]) as usize
     }
 
     #[inline]
-    fn dst_y(&self) -> usize {
-        self & 0x03E0 + DY[(self >> 10) & 0x0007]
+    pub fn src_y(&self) -> usize {
+        ((self.0 >> 5) & 0x001F) as usize
+    }
+
+    #[inline]
+    pub fn dst_y(&self) -> usize {
+        (self.src_y() as isize + DY[self.dir()]) as usize
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
-enum TileType {
+pub enum TileType {
     Plain,
     City,
     Mountain,
@@ -61,18 +88,37 @@ enum TileType {
     General,
 }
 
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tile {
+    pub kind: TileType,
+    pub owner: usize,
+    pub count: u32,
+}
+
+/// A single reversible change, recorded so search can undo moves exactly.
 #[derive(Clone, Debug)]
-struct Tile {
-    kind: TileType,
-    owner: usize,
-    count: u32,
+enum Change {
+    Tile(usize, usize, Tile),
+    /// Previous score, land, cities and liveness of a player.
+    Player(usize, u32, u32, u32, bool),
+    OwnedPush(usize),
+    /// A `swap_remove` from a player's owned tiles at the given index.
+    OwnedRemove(usize, usize, Pair),
+    /// All owned tiles of the first player appended to the second's, with the count moved.
+    OwnedTransfer(usize, usize, usize),
 }
 
+/// Position in the undo journal that `unapply_action` rolls back to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint(usize);
+
 #[derive(Clone, Debug)]
 pub struct State {
     width: usize,
     height: usize,
     player_count: usize,
+    /// Our index on the server, which decides move priority.
+    player_index: usize,
 
     tiles: Vec<Vec<Tile>>,
     global_turn: usize,
@@ -80,17 +126,25 @@ pub struct State {
     scores: Vec<u32>,
     land: Vec<u32>,
     cities: Vec<u32>,
+    alive: Vec<bool>,
 
     owned_tiles: Vec<Vec<Pair>>,
+    /// Steps to the enemy generals the game knows of, shared by every copy of the state.
+    general_distances: Vec<((usize, usize), Arc<DistanceMap>)>,
+    journal: Vec<Change>,
     search_scratch: Vec<Action>,
 }
 
 impl State {
+    /// Builds the state from our view of the game, filling in fogged tiles from its memory.
+    /// Players are renumbered so that we are always player 0, and `player_count` is the
+    /// owner of neutral tiles.
     pub fn new(game: &Game) -> Self {
         let mut state: State = State {
             width: game.width,
             height: game.height,
             player_count: game.alive.len(),
+            player_index: game.player_index,
 
             tiles: vec![],
             global_turn: game.turn as usize,
@@ -98,8 +152,11 @@ impl State {
             scores: vec![],
             land: vec![],
             cities: vec![],
+            alive: vec![],
 
             owned_tiles: vec![],
+            general_distances: vec![],
+            journal: Vec::with_capacity(1000),
             search_scratch: Vec::with_capacity(500),
         };
 
@@ -109,24 +166,51 @@ impl State {
         for i in 0..game.height {
             state.tiles.push(vec![]);
             for j in 0..game.width {
-                let value = game.raw_map[game.width * game.height + i * game.width + j];
-
-                let kind: TileType = match value {
+                let idx = i * game.width + j;
+                let mut value = game.raw_map[game.width * game.height + idx];
+                let mut count = game.raw_map[idx];
+                let mut kind: TileType = match value {
                     -2 => TileType::Mountain,
                     -3 => TileType::Fog,
                     -4 => TileType::Obstacle,
-                    -1 | _ => TileType::Plain,
+                    _ => TileType::Plain,
                 };
 
+                // Fogged tiles are as we last saw them. Our own tiles are always in sight,
+                // so one we remember as ours has been taken by someone we cannot see.
+                if value == -3 || value == -4 {
+                    match game.memory.get(idx) {
+                        Some(seen) if seen.terrain != game.player_index as i32 => {
+                            value = seen.terrain;
+                            count = seen.armies;
+                            kind = if seen.terrain == -2 {
+                                TileType::Mountain
+                            } else if seen.general {
+                                TileType::General
+                            } else if seen.city {
+                                TileType::City
+                            } else {
+                                TileType::Plain
+                            };
+                        }
+                        // A city we know of but never saw keeps its garrison to itself.
+                        _ if game.obstacles.classify(idx) == Some(Obstacle::City) => {
+                            kind = TileType::City;
+                            count = MIN_GARRISON;
+                        }
+                        _ => (),
+                    }
+                }
+
                 let owner: usize = if value < 0 {
                     num_players
                 } else {
                     (value as usize + player_shift) % num_players
                 };
                 state.tiles[i].push(Tile {
-                    kind: kind,
-                    owner: owner,
-                    count: game.raw_map[i * game.width + j] as u32,
+                    kind,
+                    owner,
+                    count: count as u32,
                 });
             }
         }
@@ -144,20 +228,38 @@ impl State {
             state.tiles[city / game.width][city % game.width].kind = TileType::City;
         }
 
-        state.scores = game.scores.clone();
-        state.land = game.tiles.clone();
-        state.cities = vec![0; game.scores.len()];
+        for (player, &general) in game.generals.iter().enumerate() {
+            if player == game.player_index || general < 0 {
+                continue;
+            }
+            let (x, y) = (general as usize % game.width, general as usize / game.width);
+            if let Some(distances) = game.terrain.cached_distances(x, y) {
+                state.general_distances.push(((x, y), distances));
+            }
+        }
+
+        state.scores = vec![0; num_players];
+        state.land = vec![0; num_players];
+        state.cities = vec![0; num_players];
+        state.alive = vec![false; num_players];
+        for i in 0..num_players {
+            let player = (i + player_shift) % num_players;
+            state.scores[player] = game.scores[i];
+            state.land[player] = game.tiles[i];
+            state.alive[player] = game.alive[i];
+        }
 
         for _ in 0..state.player_count {
             state.owned_tiles.push(Vec::with_capacity(500));
         }
         for i in 0..state.height {
             for j in 0..state.width {
-                if state.tiles[i][j].owner == 0 {
-                    state.owned_tiles[0].push(Pair { x: j, y: i });
+                let owner = state.tiles[i][j].owner;
+                if owner < state.player_count {
+                    state.owned_tiles[owner].push(Pair { x: j, y: i });
                     if state.tiles[i][j].kind == TileType::City ||
                        state.tiles[i][j].kind == TileType::General {
-                        state.cities[0] += 1;
+                        state.cities[owner] += 1;
                     }
                 }
             }
@@ -166,13 +268,93 @@ impl State {
         state
     }
 
-    pub fn select_action<T: Rng>(&mut self, rng: &mut T) -> Option<Action> {
-        const DX: [isize; 4] = [-1, 0, 1, 0];
-        const DY: [isize; 4] = [0, -1, 0, 1];
+    /// Steps from the enemy general at `(x, y)` over the walls of the game, if the game
+    /// worked them out.
+    pub fn general_distances(&self, x: usize, y: usize) -> Option<&DistanceMap> {
+        self.general_distances
+            .iter()
+            .find(|&&(general, _)| general == (x, y))
+            .map(|(_, distances)| &**distances)
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
 
-        self.search_scratch.clear();
-        for ref tile in &self.owned_tiles[0] {
-            if self.tiles[tile.y][tile.x].count <= 1 {
+    #[inline]
+    pub fn player_count(&self) -> usize {
+        self.player_count
+    }
+
+    #[inline]
+    pub fn turn(&self) -> usize {
+        self.global_turn
+    }
+
+    #[inline]
+    pub fn tile(&self, x: usize, y: usize) -> &Tile {
+        &self.tiles[y][x]
+    }
+
+    #[inline]
+    pub fn score(&self, player: usize) -> u32 {
+        self.scores[player]
+    }
+
+    #[inline]
+    pub fn land(&self, player: usize) -> u32 {
+        self.land[player]
+    }
+
+    #[inline]
+    pub fn cities(&self, player: usize) -> u32 {
+        self.cities[player]
+    }
+
+    #[inline]
+    pub fn is_alive(&self, player: usize) -> bool {
+        self.alive[player]
+    }
+
+    #[inline]
+    pub fn owned_tiles(&self, player: usize) -> &[Pair] {
+        &self.owned_tiles[player]
+    }
+
+    /// Picks a uniformly random move for us, or no move at all.
+    pub fn select_action<T: Rng>(&mut self, rng: &mut T) -> Action {
+        self.select_action_for(0, rng)
+    }
+
+    /// Picks a uniformly random move for `player`, or no move at all.
+    pub fn select_action_for<T: Rng>(&mut self, player: usize, rng: &mut T) -> Action {
+        let mut actions = ::std::mem::take(&mut self.search_scratch);
+        self.legal_actions(player, &mut actions);
+
+        let index = rng.gen_range(0, actions.len() + 1);
+        let action = if index == actions.len() {
+            // We always have the option to do nothing.
+            Action::none()
+        } else {
+            actions[index]
+        };
+        self.search_scratch = actions;
+        action
+    }
+
+    /// Fills `actions` with the moves worth considering for `player`. Moves into cities
+    /// and generals we could not take are left out, as are mountains and unknown obstacles.
+    pub fn legal_actions(&self, player: usize, actions: &mut Vec<Action>) {
+        actions.clear();
+        for tile in &self.owned_tiles[player] {
+            let count = self.tiles[tile.y][tile.x].count;
+            if count <= 1 {
                 continue;
             }
 
@@ -180,123 +362,275 @@ impl State {
                 let nx = tile.x as isize + DX[k];
                 let ny = tile.y as isize + DY[k];
                 if nx >= 0 && nx < self.width as isize && ny >= 0 && ny < self.height as isize {
-                    let nx: usize = nx as usize;
-                    let ny: usize = ny as usize;
-                    if self.tiles[ny][nx].kind == TileType::Mountain ||
-                       self.tiles[ny][nx].kind == TileType::City ||
-                       self.tiles[ny][nx].kind == TileType::Obstacle {
-                        continue;
+                    let dst = &self.tiles[ny as usize][nx as usize];
+                    match dst.kind {
+                        TileType::Mountain | TileType::Obstacle => continue,
+                        TileType::City | TileType::General
+                            if dst.owner != player && count - 1 <= dst.count => continue,
+                        _ => (),
                     }
 
-                    self.search_scratch.push(Action {
-                        half: false,
-                        src: Pair {
-                            x: tile.x,
-                            y: tile.y,
-                        },
-                        dst: Pair { x: nx, y: ny },
-                    });
-
-                    if self.tiles[tile.y][tile.x].count > 2 {
-                        self.search_scratch.push(Action {
-                            half: true,
-                            src: Pair {
-                                x: tile.x,
-                                y: tile.y,
-                            },
-                            dst: Pair { x: nx, y: ny },
-                        });
+                    actions.push(Action::new(false, tile.x, tile.y, k));
+
+                    if count > 2 {
+                        actions.push(Action::new(true, tile.x, tile.y, k));
                     }
                 }
             }
         }
+    }
 
-        let index = rng.gen_range(0, self.search_scratch.len() + 1);
-        if index == self.search_scratch.len() {
-            // We always have the option to do nothing.
-            None
+    #[inline]
+    fn save_tile(&mut self, x: usize, y: usize) {
+        self.journal.push(Change::Tile(x, y, self.tiles[y][x].clone()));
+    }
+
+    #[inline]
+    fn save_player(&mut self, player: usize) {
+        self.journal.push(Change::Player(player,
+                                         self.scores[player],
+                                         self.land[player],
+                                         self.cities[player],
+                                         self.alive[player]));
+    }
+
+    fn push_owned(&mut self, player: usize, x: usize, y: usize) {
+        self.journal.push(Change::OwnedPush(player));
+        self.owned_tiles[player].push(Pair { x, y });
+    }
+
+    fn remove_owned(&mut self, player: usize, x: usize, y: usize) {
+        let index = self.owned_tiles[player]
+            .iter()
+            .position(|tile| tile.x == x && tile.y == y)
+            .expect("Owned tile missing from owned_tiles");
+        let tile = self.owned_tiles[player].swap_remove(index);
+        self.journal.push(Change::OwnedRemove(player, index, tile));
+    }
+
+    /// Moves `action` for `player`, resolving any battle. Returns whether anything moved;
+    /// moves the player could not make are ignored, as the server does.
+    pub fn apply_move(&mut self, player: usize, action: Action) -> bool {
+        if action.is_empty() {
+            return false;
+        }
+
+        let (sx, sy) = (action.src_x(), action.src_y());
+        let (dx, dy) = (action.dst_x(), action.dst_y());
+        if sx >= self.width || sy >= self.height || dx >= self.width || dy >= self.height {
+            return false;
+        }
+
+        let src_count = self.tiles[sy][sx].count;
+        if self.tiles[sy][sx].owner != player || src_count <= 1 {
+            return false;
+        }
+        match self.tiles[dy][dx].kind {
+            TileType::Mountain | TileType::Obstacle => return false,
+            _ => (),
+        }
+
+        let moving = if action.is_half() {
+            src_count / 2
         } else {
-            Some(self.search_scratch.swap_remove(index))
+            src_count - 1
+        };
+        self.save_tile(sx, sy);
+        self.tiles[sy][sx].count -= moving;
+        self.save_tile(dx, dy);
+
+        let defender = self.tiles[dy][dx].owner;
+        if defender == player {
+            self.tiles[dy][dx].count += moving;
+            return true;
+        }
+
+        let defending = self.tiles[dy][dx].count;
+        let lost = moving.min(defending);
+        self.save_player(player);
+        self.scores[player] = self.scores[player].saturating_sub(lost);
+        if defender < self.player_count {
+            self.save_player(defender);
+            self.scores[defender] = self.scores[defender].saturating_sub(lost);
         }
-    }
 
-    pub fn unapply_action(&mut self, action: Action, counts: (u32, u32)) {
-        if self.global_turn % 50 == 0 {
-            for ref tile in &self.owned_tiles[0] {
-                self.tiles[tile.y][tile.x].count -= self.cities[0];
+        if moving <= defending {
+            self.tiles[dy][dx].count -= moving;
+            return true;
+        }
+
+        let kind = self.tiles[dy][dx].kind.clone();
+        if defender < self.player_count {
+            self.land[defender] = self.land[defender].saturating_sub(1);
+            self.remove_owned(defender, dx, dy);
+            if kind == TileType::City || kind == TileType::General {
+                self.cities[defender] -= 1;
             }
-            self.scores[0] -= self.owned_tiles[0].len() as u32 * self.cities[0];
-        } else if self.global_turn % 2 == 0 {
-            let mut count: u32 = 0;
-            for ref tile in &self.owned_tiles[0] {
-                if self.tiles[tile.y][tile.x].kind == TileType::City ||
-                   self.tiles[tile.y][tile.x].kind == TileType::General {
-                    count += 1;
-                    self.tiles[tile.y][tile.x].count -= self.cities[0];
+        }
+
+        self.tiles[dy][dx].owner = player;
+        self.tiles[dy][dx].count = moving - defending;
+        self.land[player] += 1;
+        self.push_owned(player, dx, dy);
+        match kind {
+            TileType::Fog => self.tiles[dy][dx].kind = TileType::Plain,
+            TileType::City => self.cities[player] += 1,
+            TileType::General => {
+                // Captured generals become cities of the captor.
+                self.tiles[dy][dx].kind = TileType::City;
+                self.cities[player] += 1;
+                if defender < self.player_count {
+                    self.capture_general(player, defender);
                 }
             }
-            self.scores[0] -= count * self.cities[0];
+            _ => (),
+        }
+        true
+    }
+
+    /// Hands every remaining tile of `defender` to `attacker`, halving the armies on them.
+    fn capture_general(&mut self, attacker: usize, defender: usize) {
+        let tiles = self.owned_tiles[defender].clone();
+        let mut gained: u32 = 0;
+        for tile in &tiles {
+            self.save_tile(tile.x, tile.y);
+            let tile = &mut self.tiles[tile.y][tile.x];
+            tile.owner = attacker;
+            tile.count = tile.count.div_ceil(2);
+            gained += tile.count;
         }
 
+        self.scores[attacker] += gained;
+        self.land[attacker] += self.land[defender];
+        self.cities[attacker] += self.cities[defender];
+        self.scores[defender] = 0;
+        self.land[defender] = 0;
+        self.cities[defender] = 0;
+        self.alive[defender] = false;
+
+        self.journal.push(Change::OwnedTransfer(defender, attacker, tiles.len()));
+        let moved: Vec<Pair> = self.owned_tiles[defender].drain(..).collect();
+        self.owned_tiles[attacker].extend(moved);
+    }
+
+    /// Takes `player` out of the game. Its tiles stay where they are.
+    pub fn surrender(&mut self, player: usize) {
+        self.save_player(player);
+        self.alive[player] = false;
+    }
+
+    /// Advances the turn counter and applies army growth: every second turn generals and
+    /// owned cities gain one army, every fiftieth turn every owned tile does.
+    pub fn end_turn(&mut self) {
+        self.global_turn += 1;
+        self.grow(true);
+    }
+
+    fn undo_turn(&mut self) {
+        self.grow(false);
         self.global_turn -= 1;
+    }
 
-        if !action.is_empty() {
-            let dstx: usize = action.dst_x()
-            if counts.1 == 0 {
-                self.owned_tiles[0].pop();
-                self.land[0] -= 1;
-                self.tiles[action.dst_y()][action.dst_x()].owner = self.player_count;
-            }
-            self.tiles[action.src_y()][action.src_x()].count = counts.0;
-            self.tiles[action.dst_y()][action.dst_x()].count = counts.1;
+    fn grow(&mut self, forward: bool) {
+        let cities = self.global_turn.is_multiple_of(2);
+        let land = self.global_turn.is_multiple_of(50);
+        if !cities && !land {
+            return;
         }
-    }
 
-    pub fn apply_action(&mut self, action: Action) -> (u32, u32) {
-        let mut src_before: u32 = 0;
-        let mut dst_before: u32 = 0;
+        for player in 0..self.player_count {
+            let mut total: u32 = 0;
+            for tile in &self.owned_tiles[player] {
+                let tile = &mut self.tiles[tile.y][tile.x];
+                let mut amount = 0;
+                if land {
+                    amount += 1;
+                }
+                if cities && (tile.kind == TileType::City || tile.kind == TileType::General) {
+                    amount += 1;
+                }
 
-        if !action.is_empty() {
-            src_before = self.tiles[action.src_y()][action.src_x()].count;
-            dst_before = self.tiles[action.dst_y()][action.dst_x()].count;
+                if forward {
+                    tile.count += amount;
+                } else {
+                    tile.count -= amount;
+                }
+                total += amount;
+            }
 
-            if action.is_half() {
-                self.tiles[action.dst_y()][action.dst_x()].count +=
-                    self.tiles[action.src_y()][action.src_x()].count / 2;
-                self.tiles[action.src_y()][action.src_x()].count =
-                    (self.tiles[action.src_y()][action.src_x()].count + 1) / 2;
+            if forward {
+                self.scores[player] += total;
             } else {
-                self.tiles[action.dst_y()][action.dst_x()].count +=
-                    self.tiles[action.src_y()][action.src_x()].count - 1;
-                self.tiles[action.src_y()][action.src_x()].count = 1;
+                self.scores[player] -= total;
             }
+        }
+    }
 
-            if dst_before == 0 {
-                self.land[0] += 1;
-                self.owned_tiles[0].push(action.dst.clone());
-                self.tiles[action.dst_y()][action.dst_x()].owner = 0;
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        while self.journal.len() > checkpoint.0 {
+            match self.journal.pop().unwrap() {
+                Change::Tile(x, y, tile) => self.tiles[y][x] = tile,
+                Change::Player(player, score, land, cities, alive) => {
+                    self.scores[player] = score;
+                    self.land[player] = land;
+                    self.cities[player] = cities;
+                    self.alive[player] = alive;
+                }
+                Change::OwnedPush(player) => {
+                    self.owned_tiles[player].pop();
+                }
+                Change::OwnedRemove(player, index, tile) => {
+                    let owned = &mut self.owned_tiles[player];
+                    owned.push(tile);
+                    let last = owned.len() - 1;
+                    owned.swap(index, last);
+                }
+                Change::OwnedTransfer(from, to, count) => {
+                    let split = self.owned_tiles[to].len() - count;
+                    self.owned_tiles[from] = self.owned_tiles[to].split_off(split);
+                }
             }
         }
+    }
 
-        self.global_turn += 1;
-        if self.global_turn % 50 == 0 {
-            for ref tile in &self.owned_tiles[0] {
-                self.tiles[tile.y][tile.x].count += self.cities[0];
-            }
-            self.scores[0] += self.owned_tiles[0].len() as u32 * self.cities[0];
-        } else if self.global_turn % 2 == 0 {
-            let mut count: u32 = 0;
-            for ref tile in &self.owned_tiles[0] {
-                if self.tiles[tile.y][tile.x].kind == TileType::City ||
-                   self.tiles[tile.y][tile.x].kind == TileType::General {
-                    count += 1;
-                    self.tiles[tile.y][tile.x].count += self.cities[0];
-                }
+    /// Plays `action` for us and ends the turn.
+    pub fn apply_action(&mut self, action: Action) -> Checkpoint {
+        let checkpoint = Checkpoint(self.journal.len());
+        self.apply_move(0, action);
+        self.end_turn();
+        checkpoint
+    }
+
+    /// Order the server resolves moves in for the coming turn. Priority goes by server
+    /// player index, ascending on even turns and descending on odd ones.
+    pub fn move_order(&self) -> Vec<usize> {
+        let ascending = (self.global_turn + 1).is_multiple_of(2);
+        (0..self.player_count)
+            .map(|k| {
+                let server_index = if ascending { k } else { self.player_count - 1 - k };
+                (server_index + self.player_count - self.player_index) % self.player_count
+            })
+            .collect()
+    }
+
+    /// Plays one move for every player, indexed like the players of this state, and ends
+    /// the turn. Moves are resolved one after another in priority order, so a move whose
+    /// source was taken or emptied by an earlier one that turn does nothing.
+    pub fn apply_actions(&mut self, actions: &[Action]) -> Checkpoint {
+        let checkpoint = Checkpoint(self.journal.len());
+        for player in self.move_order() {
+            if let Some(&action) = actions.get(player) {
+                self.apply_move(player, action);
             }
-            self.scores[0] += count * self.cities[0];
         }
+        self.end_turn();
+        checkpoint
+    }
 
-        (src_before, dst_before)
+    /// Reverts everything since the matching `apply_action`.
+    pub fn unapply_action(&mut self, checkpoint: Checkpoint) {
+        self.undo_turn();
+        self.rollback(checkpoint);
     }
 
     #[inline]
@@ -308,11 +642,31 @@ impl State {
 #[cfg(test)]
 mod tests {
     use game::Game;
-    use state::State;
-    use state::Action;
-    use state::Pair;
+    use state::{State, Action, TileType};
     use rand::weak_rng;
 
+    /// 3x3 map: our general at (0, 1) with 2 armies, an enemy general at (2, 1) with 5,
+    /// an enemy tile at (2, 0) and a neutral city at (1, 0) with 4.
+    fn game() -> Game {
+        Game {
+            initialized: true,
+            player_index: 0,
+            turn: 0,
+
+            width: 3,
+            height: 3,
+
+            cities: vec![1],
+            generals: vec![3, 5],
+            scores: vec![2, 7],
+            tiles: vec![1, 2],
+            alive: vec![true, true],
+
+            raw_map: vec![0, 4, 2, 2, 0, 5, 0, 0, 0, -1, -1, 1, 0, -1, 1, -1, -1, -1],
+            ..Game::new()
+        }
+    }
+
     #[test]
     fn test_apply_action1() {
         let game: Game = Game {
@@ -330,18 +684,210 @@ mod tests {
             alive: vec![true, true],
 
             raw_map: vec![0, 0, 0, 2, 0, 0, 0, 0, 0, -1, -1, -3, 0, -1, -3, -1, -1, -3],
+            ..Game::new()
         };
 
         let mut state = State::new(&game);
-        state.apply_action(&Some(Action {
-            half: false,
-            src: Pair { x: 0, y: 1 },
-            dst: Pair { x: 0, y: 0 },
-        }));
+        state.apply_action(Action::new(false, 0, 1, 1));
         println!("State: {:#?}", state);
 
         for _ in 0..10 {
             println!("Action: {:#?}", state.select_action(&mut weak_rng()));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_player_shift() {
+        let mut game = game();
+        game.player_index = 1;
+        let state = State::new(&game);
+        assert_eq!(state.tile(2, 1).owner, 0);
+        assert_eq!(state.tile(0, 1).owner, 1);
+        assert_eq!(state.score(0), 7);
+        assert_eq!(state.land(0), 2);
+        assert_eq!(state.owned_tiles(0).len(), 2);
+    }
+
+    #[test]
+    fn test_remembered_tiles() {
+        let mut game = game();
+        game.raw_map[9 + 6] = 0;
+        game.raw_map[6] = 1;
+        game.memory.update(&game.clone());
+        // The enemy falls back into fog, and takes our tile at (0, 2) unseen.
+        for idx in [2, 5, 6].iter() {
+            game.raw_map[9 + idx] = -3;
+        }
+        game.raw_map[9 + 1] = -4;
+        game.cities.clear();
+        game.generals = vec![3, -1];
+        game.raw_map[5] = 0;
+        game.raw_map[6] = 0;
+        let state = State::new(&game);
+        assert_eq!(state.tile(2, 1).kind, TileType::General);
+        assert_eq!((state.tile(2, 1).owner, state.tile(2, 1).count), (1, 5));
+        assert_eq!(state.tile(1, 0).kind, TileType::City);
+        assert_eq!(state.tile(2, 0).owner, 1);
+        assert_eq!(state.tile(0, 2).kind, TileType::Fog);
+    }
+
+    #[test]
+    fn test_attack_enemy() {
+        let mut game = game();
+        game.raw_map[3] = 8;
+        game.scores[0] = 8;
+        let mut state = State::new(&game);
+
+        // 7 armies into the empty tile at (1, 1), then 6 of them against the 5 army
+        // enemy general.
+        assert!(state.apply_move(0, Action::new(false, 0, 1, 2)));
+        assert_eq!(state.tile(1, 1).owner, 0);
+        assert_eq!(state.tile(1, 1).count, 7);
+        assert!(state.apply_move(0, Action::new(false, 1, 1, 2)));
+
+        assert_eq!(state.tile(2, 1).owner, 0);
+        assert_eq!(state.tile(2, 1).kind, TileType::City);
+        assert_eq!(state.tile(2, 1).count, 1);
+        // The enemy's other tile is ours with its 2 armies halved.
+        assert_eq!(state.tile(2, 0).owner, 0);
+        assert_eq!(state.tile(2, 0).count, 1);
+        assert!(!state.is_alive(1));
+        assert_eq!(state.land(0), 4);
+        assert_eq!(state.land(1), 0);
+        assert_eq!(state.cities(0), 2);
+        assert_eq!(state.score(0), 8 - 5 + 1);
+    }
+
+    #[test]
+    fn test_failed_attack() {
+        let mut state = State::new(&game());
+        state.apply_move(0, Action::new(false, 0, 1, 3));
+        state.apply_move(1, Action::new(false, 2, 1, 0));
+        // 3 of their 4 armies against our 1.
+        assert!(state.apply_move(1, Action::new(false, 1, 1, 0)));
+        assert_eq!(state.tile(0, 1).owner, 1);
+        assert_eq!(state.tile(0, 1).count, 2);
+        assert_eq!(state.tile(0, 2).owner, 1);
+        assert!(!state.is_alive(0));
+    }
+
+    #[test]
+    fn test_neutral_city() {
+        let mut game = game();
+        game.raw_map[3] = 10;
+        let mut state = State::new(&game);
+
+        // Half of 10 moves 5, of which 4 only wipe out the 4 army garrison.
+        assert!(state.apply_move(0, Action::new(true, 0, 1, 1)));
+        assert_eq!(state.tile(0, 1).count, 5);
+        assert!(state.apply_move(0, Action::new(false, 0, 0, 2)));
+        assert_eq!(state.tile(1, 0).owner, 2);
+        assert_eq!(state.tile(1, 0).count, 0);
+
+        assert!(state.apply_move(0, Action::new(false, 0, 1, 1)));
+        assert!(state.apply_move(0, Action::new(false, 0, 0, 2)));
+        assert_eq!(state.tile(1, 0).owner, 0);
+        assert_eq!(state.tile(1, 0).count, 4);
+        assert_eq!(state.cities(0), 2);
+    }
+
+    #[test]
+    fn test_invalid_moves() {
+        let mut game = game();
+        game.raw_map[9 + 4] = -2;
+        let mut state = State::new(&game);
+        let before = format!("{:?}", state.tiles);
+
+        // Not our tile, off the map, into a mountain and nothing to move.
+        assert!(!state.apply_move(0, Action::new(false, 2, 1, 0)));
+        assert!(!state.apply_move(0, Action::new(false, 0, 1, 0)));
+        assert!(!state.apply_move(0, Action::new(false, 0, 1, 2)));
+        assert!(!state.apply_move(0, Action::new(false, 1, 0, 3)));
+        assert_eq!(format!("{:?}", state.tiles), before);
+    }
+
+    #[test]
+    fn test_move_order() {
+        let mut game = game();
+        game.alive = vec![true; 3];
+        game.scores = vec![1; 3];
+        game.tiles = vec![1; 3];
+        game.player_index = 1;
+
+        game.turn = 1;
+        assert_eq!(State::new(&game).move_order(), vec![2, 0, 1]);
+        game.turn = 2;
+        assert_eq!(State::new(&game).move_order(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_simultaneous_moves() {
+        // We move our general's armies away to (0, 2) while the enemy, with 4 armies
+        // on (1, 1), attacks the general.
+        let mut game = game();
+        game.raw_map[3] = 9;
+        game.raw_map[4] = 4;
+        game.raw_map[9 + 4] = 1;
+        let ours = Action::new(false, 0, 1, 3);
+        let theirs = Action::new(false, 1, 1, 0);
+
+        // On odd turns player 1 moves first, and its 3 armies fail against our 9 before
+        // we move the remaining 6 away.
+        game.turn = 0;
+        let mut state = State::new(&game);
+        state.apply_actions(&[ours, theirs]);
+        assert_eq!(state.tile(0, 1).owner, 0);
+        assert_eq!(state.tile(0, 1).count, 1);
+        assert_eq!(state.tile(0, 2).count, 5);
+
+        // On even turns we move first and leave a single army behind to be overrun.
+        game.turn = 1;
+        let mut state = State::new(&game);
+        let checkpoint = state.apply_actions(&[ours, theirs]);
+        assert_eq!(state.tile(0, 1).owner, 1);
+        assert!(!state.is_alive(0));
+        assert_eq!(state.tile(0, 2).owner, 1);
+        assert_eq!(state.tile(0, 2).count, 4);
+
+        state.unapply_action(checkpoint);
+        assert_eq!(state.tile(0, 1).count, 9);
+        assert_eq!(state.tile(1, 1).owner, 1);
+        assert!(state.is_alive(0));
+    }
+
+    #[test]
+    fn test_growth() {
+        let mut game = game();
+        game.turn = 48;
+        let mut state = State::new(&game);
+        state.end_turn();
+        state.end_turn();
+        // Turn 49 adds nothing, turn 50 adds one everywhere and one more on generals.
+        assert_eq!(state.tile(0, 1).count, 2 + 2);
+        assert_eq!(state.tile(2, 1).count, 5 + 2);
+        assert_eq!(state.tile(2, 0).count, 2 + 1);
+        assert_eq!(state.score(1), 7 + 3);
+    }
+
+    #[test]
+    fn test_unapply_restores() {
+        let mut game = game();
+        game.raw_map[3] = 20;
+        game.turn = 49;
+        let mut state = State::new(&game);
+        let before = format!("{:?}", state.tiles);
+
+        let first = state.apply_action(Action::new(false, 0, 1, 2));
+        let second = state.apply_action(Action::new(false, 1, 1, 2));
+        assert!(!state.is_alive(1));
+        state.unapply_action(second);
+        state.unapply_action(first);
+
+        assert_eq!(format!("{:?}", state.tiles), before);
+        assert_eq!(state.turn(), 49);
+        assert!(state.is_alive(1));
+        assert_eq!(state.owned_tiles(1).len(), 2);
+        assert_eq!(state.land(0), 1);
+        assert_eq!(state.score(0), 2);
+    }
+}
@@ -1,15 +1,114 @@
+use config::{BotKind, StrategyConfig};
+use eval::{Evaluator, WeightedEvaluator};
 use game::Game;
-use state::{State, Action, Pair};
+use gather;
+use greedy::Greedy;
+use opening::Opening;
+use state::{State, Action};
 use time::precise_time_ns;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
-use std::f32;
+use std::mem;
+use std::thread;
 use rand::{weak_rng, Rng, XorShiftRng};
 use std::fmt;
 
+/// A bot, as the live client and the offline arena drive it. Both call the hooks in the
+/// same order: `game_start` once, then `observe` with every update, `next_move` when a
+/// move is wanted, and finally `game_end`.
+pub trait Strategy: Send {
+    /// A new game began. `game` holds the players but no map yet.
+    fn game_start(&mut self, _game: &Game) {}
+
+    /// `game` has just been updated. Updates are seen in order even when the bot was
+    /// too busy to move on some of them.
+    fn observe(&mut self, _game: &Game) {}
+
+    /// The move to make on `game`, decided by `deadline` in `precise_time_ns` time.
+    fn next_move(&mut self, game: &Game, deadline: u64) -> Action;
+
+    /// The game is over, won or lost.
+    fn game_end(&mut self, _won: bool) {}
+}
+
+/// Builds the bot `config` asks for. Bots other than the random one leave the opening
+/// to the opening planner, if it has any turns.
+pub fn create(config: &StrategyConfig) -> Box<dyn Strategy> {
+    let bot: Box<dyn Strategy> = match config.bot {
+        BotKind::Random => return Box::new(RandomBot::new()),
+        BotKind::Greedy => Box::new(Greedy::new()),
+        BotKind::MonteCarlo => Box::new(MonteCarlo::new(config)),
+    };
+    if config.opening_turns > 0 {
+        Box::new(Opening::new(config.opening_turns, bot))
+    } else {
+        bot
+    }
+}
+
+/// Plays a random legal move every turn, the weakest sparring partner there is.
+pub struct RandomBot {
+    rng: XorShiftRng,
+}
+
+impl RandomBot {
+    pub fn new() -> Self {
+        RandomBot { rng: weak_rng() }
+    }
+}
+
+impl Default for RandomBot {
+    fn default() -> Self {
+        RandomBot::new()
+    }
+}
+
+impl Strategy for RandomBot {
+    fn next_move(&mut self, game: &Game, _deadline: u64) -> Action {
+        State::new(game).select_action(&mut self.rng)
+    }
+}
+
+/// How we move in the random walks below the tree: along the gather into our largest
+/// stack planned at the root, one move a turn for as long as it can be made, and at
+/// random after that. Random walks alone almost never bring armies together.
+#[derive(Clone, Debug, Default)]
+struct Rollout {
+    /// The turn the gather starts on.
+    start: usize,
+    gather: Vec<Action>,
+}
+
+impl Rollout {
+    fn new(state: &State, turns: usize) -> Self {
+        let stack = state.owned_tiles(0)
+            .iter()
+            .max_by_key(|tile| state.tile(tile.x, tile.y).count)
+            .map(|tile| (tile.x, tile.y));
+        Rollout {
+            start: state.turn(),
+            gather: stack.map_or_else(Vec::new, |stack| gather::plan(state, 0, stack, turns).moves),
+        }
+    }
+
+    /// Our move on the turn `state` is at.
+    fn action<T: Rng>(&self, rng: &mut T, state: &mut State) -> Action {
+        let planned = state.turn()
+            .checked_sub(self.start)
+            .and_then(|turn| self.gather.get(turn).cloned())
+            .filter(|action| {
+                let tile = state.tile(action.src_x(), action.src_y());
+                tile.owner == 0 && tile.count > 1
+            });
+        planned.unwrap_or_else(|| state.select_action(rng))
+    }
+}
+
 #[derive(Debug)]
 struct ActionTransfer {
     visit_count: usize,
-    cumulative_score: i32,
+    /// Sum of the rewards of every walk through this action, each between 0 and 1.
+    cumulative_score: f64,
 
     next_node: SearchNode,
 }
@@ -18,12 +117,18 @@ impl Default for ActionTransfer {
     fn default() -> ActionTransfer {
         ActionTransfer {
             visit_count: 0,
-            cumulative_score: 0,
+            cumulative_score: 0.0,
             next_node: SearchNode::new(),
         }
     }
 }
 
+impl ActionTransfer {
+    fn mean_score(&self) -> f64 {
+        self.cumulative_score / self.visit_count as f64
+    }
+}
+
 #[derive(Debug)]
 struct SearchNode {
     actions: BTreeMap<Action, ActionTransfer>,
@@ -34,95 +139,533 @@ impl SearchNode {
         SearchNode { actions: BTreeMap::new() }
     }
 
-    pub fn search<T: Rng>(&mut self, rng: &mut T, state: &mut State, depth: i32) -> i32 {
+    /// Walks the tree down to `depth` turns ahead and returns the reward `evaluator`
+    /// gives the position found there. The first time an action is tried from a node
+    /// the walk continues as `rollout` plays, so every walk adds at most one node to the
+    /// tree.
+    pub fn search<T: Rng>(&mut self,
+                          rng: &mut T,
+                          state: &mut State,
+                          depth: i32,
+                          exploration: f64,
+                          evaluator: &dyn Evaluator,
+                          rollout: &Rollout)
+                          -> f64 {
         if depth == 0 {
-            return state.evaluate();
+            return evaluator.evaluate(state);
         }
 
-        let action = self.select_action(rng, state);
-        let counts_before = state.apply_action(action);
+        let (action, expanded) = self.select_action(rng, state, exploration);
+        let actions = SearchNode::turn_actions(rng, state, action);
+        let checkpoint = state.apply_actions(&actions);
         let transfer: &mut ActionTransfer = self.get_action_transfer(action);
-        let score = transfer.next_node.search(rng, state, depth - 1);
-        state.unapply_action(action, counts_before);
+        let score = if expanded {
+            SearchNode::rollout(rng, state, depth - 1, evaluator, rollout)
+        } else {
+            transfer.next_node.search(rng, state, depth - 1, exploration, evaluator, rollout)
+        };
+        state.unapply_action(checkpoint);
         transfer.visit_count += 1;
         transfer.cumulative_score += score;
-        score as i32
+        score
     }
 
-    pub fn select_action<T: Rng>(&self, rng: &mut T, state: &mut State) -> Action {
-        state.select_action(rng)
+    /// Our action alongside a random move for every opponent still in the game.
+    fn turn_actions<T: Rng>(rng: &mut T, state: &mut State, action: Action) -> Vec<Action> {
+        let mut actions = Vec::with_capacity(state.player_count());
+        actions.push(action);
+        for player in 1..state.player_count() {
+            if state.is_alive(player) {
+                actions.push(state.select_action_for(player, rng));
+            } else {
+                actions.push(Action::none());
+            }
+        }
+        actions
+    }
+
+    /// Plays `depth` turns, our moves as `rollout` has them and random ones for everyone
+    /// else, and scores where that ends up.
+    fn rollout<T: Rng>(rng: &mut T,
+                       state: &mut State,
+                       depth: i32,
+                       evaluator: &dyn Evaluator,
+                       rollout: &Rollout)
+                       -> f64 {
+        let mut checkpoints = Vec::with_capacity(depth as usize);
+        for _ in 0..depth {
+            let action = rollout.action(rng, state);
+            let actions = SearchNode::turn_actions(rng, state, action);
+            checkpoints.push(state.apply_actions(&actions));
+        }
+        let score = evaluator.evaluate(state);
+        for checkpoint in checkpoints.into_iter().rev() {
+            state.unapply_action(checkpoint);
+        }
+        score
+    }
+
+    /// Picks a random action not yet tried from this node while there are any, and the
+    /// one maximising UCB1 after that. Opponents move at random, so the same node can
+    /// be reached in different states and only the actions legal now are considered.
+    /// Returns whether the action is being tried for the first time.
+    pub fn select_action<T: Rng>(&self,
+                                 rng: &mut T,
+                                 state: &mut State,
+                                 exploration: f64)
+                                 -> (Action, bool) {
+        let mut legal = vec![];
+        state.legal_actions(0, &mut legal);
+        // We always have the option to do nothing.
+        legal.push(Action::none());
+
+        let untried = legal.iter().filter(|action| !self.actions.contains_key(action)).count();
+        if untried > 0 {
+            let pick = rng.gen_range(0, untried);
+            let action = legal.iter()
+                .filter(|action| !self.actions.contains_key(action))
+                .nth(pick)
+                .unwrap();
+            return (*action, true);
+        }
+
+        let visits: usize = legal.iter().map(|action| self.actions[action].visit_count).sum();
+        let log_visits = (visits as f64).ln();
+        let mut best_score = f64::MIN;
+        let mut best_action = Action::none();
+        for action in &legal {
+            let transfer = &self.actions[action];
+            let score = transfer.mean_score() +
+                        exploration * (log_visits / transfer.visit_count as f64).sqrt();
+            if score > best_score {
+                best_score = score;
+                best_action = *action;
+            }
+        }
+        (best_action, false)
+    }
+
+    /// Total walks through this node.
+    fn visit_count(&self) -> usize {
+        self.actions.values().map(|transfer| transfer.visit_count).sum()
+    }
+
+    /// Drops the actions that cannot be played in `state`, along with everything learnt
+    /// below them.
+    fn prune(&mut self, state: &State) {
+        let mut legal = vec![];
+        state.legal_actions(0, &mut legal);
+        legal.push(Action::none());
+        legal.sort();
+        self.actions.retain(|action, _| legal.binary_search(action).is_ok());
     }
 
     pub fn get_action_transfer(&mut self, action: Action) -> &mut ActionTransfer {
-        self.actions.entry(action).or_insert_with(ActionTransfer::default)
+        self.actions.entry(action).or_default()
     }
 
-    pub fn select_best_move(&self) -> Action {
-        let mut best_score: f32 = f32::MIN;
-        let mut best_action: Option<Action> = None;
-        for (action, transfer) in &self.actions {
-            let next_score = transfer.cumulative_score as f32 / transfer.visit_count as f32;
-            if next_score > best_score {
-                best_score = next_score;
-                best_action = Some(action);
+    /// The node below `action`, without the actions that cannot be played in `state`.
+    fn advance(mut self, action: Action, state: &State) -> SearchNode {
+        match self.actions.remove(&action) {
+            Some(transfer) => {
+                let mut node = transfer.next_node;
+                node.prune(state);
+                node
             }
+            None => SearchNode::new(),
         }
+    }
+}
 
-        best_action.unwrap()
+/// An independent search with its own tree and random stream. Each thread runs one, and
+/// only their root statistics are combined.
+struct Searcher {
+    root: SearchNode,
+    rng: XorShiftRng,
+}
+
+impl Searcher {
+    fn new() -> Self {
+        Searcher {
+            root: SearchNode::new(),
+            rng: weak_rng(),
+        }
+    }
+
+    /// Walks the tree until `deadline`, at least once, and returns the number of walks.
+    fn run(&mut self,
+           state: &mut State,
+           depth: i32,
+           exploration: f64,
+           evaluator: &dyn Evaluator,
+           rollout: &Rollout,
+           deadline: u64)
+           -> usize {
+        let mut count = 0;
+        loop {
+            count += 1;
+            self.root.search(&mut self.rng, state, depth, exploration, evaluator, rollout);
+            if precise_time_ns() >= deadline {
+                return count;
+            }
+        }
+    }
+}
+
+/// The action most visited over all the searchers' roots, which UCB1 converges on. Ties
+/// go to the better mean.
+fn select_best_move(searchers: &[Searcher]) -> Action {
+    let mut totals: BTreeMap<Action, (usize, f64)> = BTreeMap::new();
+    for searcher in searchers {
+        for (action, transfer) in &searcher.root.actions {
+            let total = totals.entry(*action).or_insert((0, 0.0));
+            total.0 += transfer.visit_count;
+            total.1 += transfer.cumulative_score;
+        }
+    }
+
+    totals.iter()
+        .map(|(action, &(visits, score))| (*action, visits, score / visits as f64))
+        .max_by(|a, b| (a.1, a.2).partial_cmp(&(b.1, b.2)).unwrap_or(Ordering::Equal))
+        .map(|(action, _, _)| action)
+        .unwrap_or_else(Action::none)
+}
+
+/// The position a search ended in: enough to tell whether the next one follows on.
+#[derive(Clone, Debug, PartialEq)]
+struct LastMove {
+    turn: i32,
+    width: usize,
+    height: usize,
+    player_index: usize,
+    action: Action,
+    /// The owner and armies of the tiles `action` touches, as the tree expects them next
+    /// turn when nobody else comes near.
+    expected: Vec<((usize, usize), usize, u32)>,
+}
+
+impl LastMove {
+    fn new(game: &Game, state: &mut State, action: Action) -> Self {
+        let mut expected = vec![];
+        if !action.is_empty() {
+            let mut actions = vec![Action::none(); state.player_count()];
+            actions[0] = action;
+            let checkpoint = state.apply_actions(&actions);
+            for &(x, y) in &[(action.src_x(), action.src_y()), (action.dst_x(), action.dst_y())] {
+                let tile = state.tile(x, y);
+                expected.push(((x, y), tile.owner, tile.count));
+            }
+            state.unapply_action(checkpoint);
+        }
+        LastMove {
+            turn: game.turn,
+            width: game.width,
+            height: game.height,
+            player_index: game.player_index,
+            action,
+            expected,
+        }
     }
 }
 
 pub struct MonteCarlo {
-    root: Option<SearchNode>,
-    rng: XorShiftRng,
+    config: StrategyConfig,
+    evaluator: Box<dyn Evaluator>,
+    searchers: Vec<Searcher>,
+    last_move: Option<LastMove>,
 }
 
 impl fmt::Debug for MonteCarlo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.root.fmt(f)
+        f.debug_list().entries(self.searchers.iter().map(|searcher| &searcher.root)).finish()
     }
 }
 
 impl MonteCarlo {
-    pub fn new() -> Self {
+    pub fn new(config: &StrategyConfig) -> Self {
         MonteCarlo {
-            root: None,
-            rng: weak_rng(),
+            config: config.clone(),
+            evaluator: Box::new(WeightedEvaluator::new(&config.weights)),
+            searchers: (0..config.threads.max(1)).map(|_| Searcher::new()).collect(),
+            last_move: None,
         }
     }
 
-    pub fn next_move(&mut self, game: &Game) -> Action {
+    pub fn config(&self) -> &StrategyConfig {
+        &self.config
+    }
+
+    /// Our last move, when `game` is the turn right after it, `state` shows the tiles it
+    /// touched as the tree expected, and the trees below it can carry on. Those already
+    /// hold walks from this position: the opponents' moves are not part of the tree, so
+    /// only our actions that are no longer legal need to go. Anything else, including a
+    /// move the server did not make as planned or a new horizon whose rewards are
+    /// measured at a different turn, starts over.
+    fn followed_move(&mut self, game: &Game, state: &State) -> Option<Action> {
+        let last_move = self.last_move.take()?;
+        let follows = last_move.turn + 1 == game.turn && last_move.width == game.width &&
+                      last_move.height == game.height &&
+                      last_move.player_index == game.player_index;
+        let as_expected = |&((x, y), owner, count): &((usize, usize), usize, u32)| {
+            let tile = state.tile(x, y);
+            tile.owner == owner && tile.count == count
+        };
+        if follows && last_move.expected.iter().all(as_expected) &&
+           game.turn % self.config.horizon != 0 {
+            Some(last_move.action)
+        } else {
+            None
+        }
+    }
+
+    /// Drops the trees, which hold nothing of use for another game.
+    fn reset(&mut self) {
+        for searcher in &mut self.searchers {
+            searcher.root = SearchNode::new();
+        }
+        self.last_move = None;
+    }
+}
+
+impl Strategy for MonteCarlo {
+    fn game_start(&mut self, _game: &Game) {
+        self.reset();
+    }
+
+    /// Searches until `deadline` and returns the best move found. Every thread makes at
+    /// least one walk however little time is left.
+    fn next_move(&mut self, game: &Game, deadline: u64) -> Action {
         let start = precise_time_ns();
 
-        self.root = Some(SearchNode::new());
         let mut state = State::new(game);
-
-        let mut count = 0;
-        let depth = 50 - (game.turn % 50);
-        loop {
-            count += 1;
-            self.root.as_mut().unwrap().search(&mut self.rng, &mut state, depth);
-            if precise_time_ns() - start > 200_000_00000 {
-                break;
+        let followed = self.followed_move(game, &state);
+        for searcher in &mut self.searchers {
+            let root = mem::replace(&mut searcher.root, SearchNode::new());
+            if let Some(action) = followed {
+                searcher.root = root.advance(action, &state);
             }
         }
+        let reused: usize = self.searchers.iter().map(|s| s.root.visit_count()).sum();
+
+        let depth = self.config.horizon - (game.turn % self.config.horizon);
+        let exploration = self.config.exploration;
+        let evaluator = &*self.evaluator;
+        let rollout = &Rollout::new(&state, depth as usize);
+        let searchers = &mut self.searchers;
+        let count: usize = if searchers.len() == 1 {
+            searchers[0].run(&mut state, depth, exploration, evaluator, rollout, deadline)
+        } else {
+            let state = &state;
+            thread::scope(|scope| {
+                let threads: Vec<_> = searchers.iter_mut()
+                    .map(|searcher| {
+                        scope.spawn(move || {
+                            let mut state = state.clone();
+                            searcher.run(&mut state,
+                                         depth,
+                                         exploration,
+                                         evaluator,
+                                         rollout,
+                                         deadline)
+                        })
+                    })
+                    .collect();
+                threads.into_iter().map(|thread| thread.join().unwrap()).sum()
+            })
+        };
 
-        println!("Sampled {:?} walks for next move. Depth as {:#?} {:#?} {:#?}.",
-                 &count,
+        println!("Sampled {} walks on {} threads for next move in {}ms, depth {}, reusing {}.",
+                 count,
+                 self.searchers.len(),
+                 (precise_time_ns() - start) / 1_000_000,
                  depth,
-                 start,
-                 precise_time_ns());
-        self.root.as_ref().unwrap().select_best_move();
+                 reused);
+        let action = select_best_move(&self.searchers);
+        self.last_move = Some(LastMove::new(game, &mut state, action));
+        action
+    }
+
+    fn game_end(&mut self, _won: bool) {
+        self.reset();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use strategy::MonteCarlo;
+    use arena::Observer;
+    use strategy::{self, LastMove, MonteCarlo, Rollout, SearchNode, Strategy};
+    use config::{BotKind, StrategyConfig, Weights};
+    use eval::WeightedEvaluator;
     use game::Game;
-    use state::State;
-    use state::Action;
-    use state::Pair;
+    use rand::weak_rng;
+    use state::{Action, State};
+    use std::mem;
+    use time::precise_time_ns;
+
+    /// 3x3 map: our general at (0, 1) with 10 armies next to the enemy general at (1, 1)
+    /// with 1, and an enemy tile at (2, 2).
+    fn capture() -> Game {
+        Game {
+            initialized: true,
+            player_index: 0,
+            turn: 0,
+
+            width: 3,
+            height: 3,
+
+            cities: vec![],
+            generals: vec![3, 4],
+            scores: vec![10, 2],
+            tiles: vec![1, 2],
+            alive: vec![true, true],
+
+            raw_map: vec![0, 0, 0, 10, 1, 0, 0, 0, 1, -1, -1, -1, 0, 1, -1, -1, -1, 1],
+            ..Game::new()
+        }
+    }
+
+    #[test]
+    fn test_expands_every_action() {
+        let mut state = State::new(&capture());
+        let mut legal = vec![];
+        state.legal_actions(0, &mut legal);
+        let options = legal.len() + 1;
+
+        let mut rng = weak_rng();
+        let evaluator = WeightedEvaluator::new(&Weights::default());
+        let rollout = Rollout::new(&state, 3);
+        let mut root = SearchNode::new();
+        for _ in 0..options {
+            root.search(&mut rng, &mut state, 3, 1.0, &evaluator, &rollout);
+        }
+        // Every walk so far tried a new action, and only added that one node.
+        assert_eq!(root.actions.len(), options);
+        assert!(root.actions.values().all(|t| t.visit_count == 1));
+        assert!(root.actions.values().all(|t| t.next_node.actions.is_empty()));
+        assert!(root.actions.values().all(|t| t.cumulative_score <= 1.0));
+
+        root.search(&mut rng, &mut state, 3, 1.0, &evaluator, &rollout);
+        assert_eq!(root.actions.values().map(|t| t.visit_count).sum::<usize>(), options + 1);
+    }
+
+    #[test]
+    fn test_rollout() {
+        // Our 6 armies at (0, 0) are brought to our general at (0, 1).
+        let mut game = capture();
+        game.raw_map[0] = 6;
+        game.raw_map[9] = 0;
+        let mut state = State::new(&game);
+        let rollout = Rollout::new(&state, 4);
+        let gather = Action::new(false, 0, 0, 3);
+        assert_eq!(rollout.gather, vec![gather]);
+
+        let mut rng = weak_rng();
+        assert_eq!(rollout.action(&mut rng, &mut state), gather);
+        // Once the plan runs out, or cannot be followed, the walk goes on at random.
+        state.apply_actions(&[gather, Action::none()]);
+        let mut legal = vec![];
+        state.legal_actions(0, &mut legal);
+        for _ in 0..10 {
+            let action = rollout.action(&mut rng, &mut state);
+            assert!(action.is_empty() || legal.contains(&action));
+        }
+    }
+
+    #[test]
+    fn test_reuse_tree() {
+        let config = StrategyConfig {
+            horizon: 10,
+            threads: 1,
+            ..StrategyConfig::default()
+        };
+        let mut search = MonteCarlo::new(&config);
+        let mut start = capture();
+        start.raw_map[4] = 20;
+        let mut state = State::new(&start);
+        let mut observer = Observer::new();
+        let mut game = Game::new();
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+        let action = search.next_move(&game, precise_time_ns() + 100_000_000);
+        let below = search.searchers[0].root.actions[&action].next_node.visit_count();
+        assert!(below > 0);
+
+        // The next turn keeps what was learnt below our move, minus illegal moves.
+        state.apply_actions(&[action, Action::none()]);
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+        let state = State::new(&game);
+        assert_eq!(search.followed_move(&game, &state), Some(action));
+        let root = mem::replace(&mut search.searchers[0].root, SearchNode::new());
+        let root = root.advance(action, &state);
+        assert!(root.visit_count() > 0 && root.visit_count() <= below);
+        let mut legal = vec![];
+        state.legal_actions(0, &mut legal);
+        assert!(root.actions.keys().all(|a| a.is_empty() || legal.contains(a)));
+
+        // A move the server did not make, skipped turns and a new horizon start over.
+        let down = Action::new(false, 0, 1, 3);
+        search.last_move = Some(LastMove::new(&game, &mut State::new(&game), down));
+        let mut state = State::new(&game);
+        state.apply_actions(&[Action::none(), Action::none()]);
+        game.handle_game_update(&observer.update(&state, 0)).unwrap();
+        assert_eq!(search.followed_move(&game, &state), None);
+        search.next_move(&game, 0);
+        game.turn += 2;
+        assert_eq!(search.followed_move(&game, &state), None);
+        search.next_move(&game, 0);
+        game.turn = 10;
+        search.last_move.as_mut().unwrap().turn = 9;
+        assert_eq!(search.followed_move(&game, &state), None);
+    }
+
+    #[test]
+    fn test_parallel() {
+        let mut search = MonteCarlo::new(&StrategyConfig {
+            horizon: 4,
+            threads: 4,
+            ..StrategyConfig::default()
+        });
+        let action = search.next_move(&capture(), precise_time_ns() + 200_000_000);
+        assert_eq!((action.dst_x(), action.dst_y()), (1, 1));
+        assert_eq!(search.searchers.len(), 4);
+        assert!(search.searchers.iter().all(|searcher| searcher.root.visit_count() > 0));
+    }
+
+    #[test]
+    fn test_finds_capture() {
+        let mut search = MonteCarlo::new(&StrategyConfig {
+            horizon: 4,
+            ..StrategyConfig::default()
+        });
+        let action = search.next_move(&capture(), precise_time_ns() + 200_000_000);
+        assert!(!action.is_empty());
+        assert_eq!((action.src_x(), action.src_y()), (0, 1));
+        assert_eq!((action.dst_x(), action.dst_y()), (1, 1));
+    }
+
+    #[test]
+    fn test_bots() {
+        let game = capture();
+        let mut legal = vec![];
+        State::new(&game).legal_actions(0, &mut legal);
+        let mut random = strategy::create(&StrategyConfig {
+            bot: BotKind::Random,
+            ..StrategyConfig::default()
+        });
+        for _ in 0..20 {
+            let action = random.next_move(&game, 0);
+            assert!(action.is_empty() || legal.contains(&action));
+        }
+
+        // Trees from one game are not carried into the next.
+        let mut search = MonteCarlo::new(&StrategyConfig {
+            threads: 1,
+            ..StrategyConfig::default()
+        });
+        search.next_move(&game, 0);
+        search.game_end(true);
+        assert_eq!(search.searchers[0].root.visit_count(), 0);
+        assert!(search.last_move.is_none());
+    }
 
     #[test]
     fn test_large() {
@@ -174,15 +717,16 @@ mod tests {
             scores: vec![3, 3],
             tiles: vec![1, 1],
             alive: vec![false, false],
+            ..Game::new()
         };
 
-        let mut search: MonteCarlo = MonteCarlo::new();
-        let next_move = search.next_move(&game);
+        let mut search: MonteCarlo = MonteCarlo::new(&StrategyConfig::default());
+        search.next_move(&game, precise_time_ns() + 200_000_000);
     }
 
     #[test]
     fn test_search() {
-        let mut search: MonteCarlo = MonteCarlo::new();
+        let mut search: MonteCarlo = MonteCarlo::new(&StrategyConfig::default());
         let game: Game = Game {
             initialized: true,
             player_index: 0,
@@ -198,9 +742,10 @@ mod tests {
             alive: vec![true, true],
 
             raw_map: vec![0, 0, 0, 2, 0, 0, 0, 0, 0, -1, -1, -3, 0, -1, -3, -1, -1, -3],
+            ..Game::new()
         };
 
-        let next_move = search.next_move(&game);
+        let next_move = search.next_move(&game, precise_time_ns() + 200_000_000);
         println!("Next move: {:?}", next_move);
     }
 }
\ No newline at end of file
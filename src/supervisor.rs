@@ -0,0 +1,81 @@
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+
+pub const RECONNECT_MIN_DELAY_MS: u64 = 1_000;
+pub const RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+
+/// How a single connection attempt ended.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The connection never got as far as a working session.
+    Failed,
+    /// A working session was lost and should be re-established.
+    Disconnected,
+    /// The client is done and should not reconnect.
+    Finished,
+}
+
+/// Exponential reconnect delay, doubling after every consecutive failure up to a cap.
+#[derive(Debug)]
+pub struct Backoff {
+    min_delay: u64,
+    max_delay: u64,
+    next_delay: u64,
+}
+
+impl Backoff {
+    pub fn new(min_delay: u64, max_delay: u64) -> Self {
+        Backoff {
+            min_delay,
+            max_delay,
+            next_delay: min_delay,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> u64 {
+        let delay = self.next_delay;
+        self.next_delay = cmp::min(self.next_delay.saturating_mul(2), self.max_delay);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next_delay = self.min_delay;
+    }
+}
+
+/// Runs `connect_once` until it reports `Finished`, sleeping with exponential backoff
+/// between attempts. `connect_once` blocks for the lifetime of a connection; after one
+/// that got as far as a working session the backoff starts over from the minimum.
+pub fn supervise<F: FnMut() -> Outcome>(mut connect_once: F) {
+    let mut backoff = Backoff::new(RECONNECT_MIN_DELAY_MS, RECONNECT_MAX_DELAY_MS);
+    loop {
+        match connect_once() {
+            Outcome::Finished => return,
+            Outcome::Disconnected => backoff.reset(),
+            Outcome::Failed => (),
+        }
+
+        let delay = backoff.next_delay();
+        println!("Connection lost, reconnecting in {}ms.", delay);
+        thread::sleep(Duration::from_millis(delay));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use supervisor::Backoff;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(100, 500);
+        assert_eq!(backoff.next_delay(), 100);
+        assert_eq!(backoff.next_delay(), 200);
+        assert_eq!(backoff.next_delay(), 400);
+        assert_eq!(backoff.next_delay(), 500);
+        assert_eq!(backoff.next_delay(), 500);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), 100);
+    }
+}
@@ -0,0 +1,208 @@
+//! How long the search may think about a move. The server plays a turn every tick, and a
+//! move has to reach it before the next one, so the search works to a deadline derived
+//! from when the last `game_update` arrived and how fast the game is running.
+
+use config::StrategyConfig;
+use game::Game;
+
+/// Turn length at normal game speed, assumed until updates show otherwise.
+pub const DEFAULT_TURN_MS: u64 = 500;
+
+/// Observed turn lengths outside these bounds are stalls or reconnects, not game speed.
+const MIN_TURN_MS: u64 = 50;
+const MAX_TURN_MS: u64 = 2_000;
+
+/// Every owned tile grows on multiples of this many turns.
+const GROWTH_INTERVAL: i32 = 50;
+/// Turns before growth on which taking more land pays off right away.
+const GROWTH_WINDOW: i32 = 4;
+/// Enemy armies this close to our general are a threat when they outnumber it.
+const THREAT_DISTANCE: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    search_time_ns: u64,
+    latency_ns: u64,
+    critical_factor: f64,
+    turn_ns: u64,
+    /// Turn and arrival time of the last update.
+    last_update: Option<(i32, u64)>,
+}
+
+impl TimeManager {
+    pub fn new(config: &StrategyConfig) -> Self {
+        TimeManager {
+            search_time_ns: config.search_time_ms * 1_000_000,
+            latency_ns: config.latency_ms * 1_000_000,
+            critical_factor: config.critical_factor,
+            turn_ns: DEFAULT_TURN_MS * 1_000_000,
+            last_update: None,
+        }
+    }
+
+    /// Forgets the last game, whose speed may differ from the next one's.
+    pub fn reset(&mut self) {
+        self.turn_ns = DEFAULT_TURN_MS * 1_000_000;
+        self.last_update = None;
+    }
+
+    /// Estimated time between two turns, in milliseconds.
+    pub fn turn_ms(&self) -> u64 {
+        self.turn_ns / 1_000_000
+    }
+
+    /// Records that the update for `turn` arrived at `now`, in `precise_time_ns` time,
+    /// and refines the estimated turn length from the time since the previous one.
+    pub fn observe_update(&mut self, turn: i32, now: u64) {
+        if let Some((last_turn, last_time)) = self.last_update {
+            if turn > last_turn && now > last_time {
+                let interval = (now - last_time) / (turn - last_turn) as u64;
+                if (MIN_TURN_MS * 1_000_000..=MAX_TURN_MS * 1_000_000).contains(&interval) {
+                    self.turn_ns = (3 * self.turn_ns + interval) / 4;
+                }
+            }
+        }
+        self.last_update = Some((turn, now));
+    }
+
+    /// When a search starting at `now` has to stop. Searches get `search_time_ms`, more
+    /// on critical turns, but never run past the point where the move would miss the
+    /// next turn. Without any update observed there is no turn to miss.
+    pub fn deadline(&self, game: &Game, now: u64) -> u64 {
+        let mut budget = self.search_time_ns;
+        if is_critical(game) {
+            budget = (budget as f64 * self.critical_factor) as u64;
+        }
+
+        let deadline = now + budget;
+        match self.last_update {
+            Some((_, last_time)) => {
+                let next_turn = last_time + self.turn_ns;
+                deadline.min(next_turn.saturating_sub(self.latency_ns).max(now))
+            }
+            None => deadline,
+        }
+    }
+}
+
+/// Whether the coming move deserves extra thought: the last turns before all land grows,
+/// and turns where visible enemy armies near our general outnumber it.
+pub fn is_critical(game: &Game) -> bool {
+    if game.turn % GROWTH_INTERVAL >= GROWTH_INTERVAL - GROWTH_WINDOW {
+        return true;
+    }
+
+    let general = match game.generals.get(game.player_index) {
+        Some(&general) if general >= 0 => general as usize,
+        _ => return false,
+    };
+    let size = game.width * game.height;
+    let (gx, gy) = (general % game.width, general / game.width);
+    let defenders = game.raw_map[general];
+
+    for y in gy.saturating_sub(THREAT_DISTANCE)..(gy + THREAT_DISTANCE + 1).min(game.height) {
+        for x in gx.saturating_sub(THREAT_DISTANCE)..(gx + THREAT_DISTANCE + 1).min(game.width) {
+            let distance = x.max(gx) - x.min(gx) + y.max(gy) - y.min(gy);
+            let idx = y * game.width + x;
+            let owner = game.raw_map[size + idx];
+            if distance <= THREAT_DISTANCE && owner >= 0 && owner as usize != game.player_index &&
+               game.raw_map[idx] > defenders {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use config::StrategyConfig;
+    use game::Game;
+    use timing::{is_critical, TimeManager};
+
+    const MS: u64 = 1_000_000;
+
+    /// 5x5 map with our general at (0, 0) holding 5 armies and nothing else in sight.
+    fn game() -> Game {
+        let mut raw_map = vec![0; 50];
+        raw_map[0] = 5;
+        for terrain in &mut raw_map[25..] {
+            *terrain = -3;
+        }
+        raw_map[25] = 0;
+        Game {
+            initialized: true,
+            raw_map,
+            player_index: 0,
+            turn: 10,
+            width: 5,
+            height: 5,
+            generals: vec![0, -1],
+            cities: vec![],
+            scores: vec![5, 5],
+            tiles: vec![1, 1],
+            alive: vec![true, true],
+            ..Game::new()
+        }
+    }
+
+    fn config() -> StrategyConfig {
+        StrategyConfig {
+            search_time_ms: 100,
+            latency_ms: 50,
+            critical_factor: 3.0,
+            ..StrategyConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_turn_estimate() {
+        let mut timer = TimeManager::new(&config());
+        assert_eq!(timer.turn_ms(), 500);
+        timer.observe_update(1, 1_000 * MS);
+        timer.observe_update(2, 1_250 * MS);
+        assert_eq!(timer.turn_ms(), 437);
+        // A long stall is not taken for the game speed.
+        timer.observe_update(3, 9_000 * MS);
+        assert_eq!(timer.turn_ms(), 437);
+        timer.reset();
+        assert_eq!(timer.turn_ms(), 500);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut timer = TimeManager::new(&config());
+        let mut game = game();
+        assert_eq!(timer.deadline(&game, 0), 100 * MS);
+
+        game.turn = 48;
+        assert_eq!(timer.deadline(&game, 0), 300 * MS);
+
+        // The update arrived at 1s, so the move has to leave by 1.45s.
+        timer.observe_update(48, 1_000 * MS);
+        assert_eq!(timer.deadline(&game, 1_000 * MS), 1_300 * MS);
+        assert_eq!(timer.deadline(&game, 1_200 * MS), 1_450 * MS);
+        assert_eq!(timer.deadline(&game, 1_600 * MS), 1_600 * MS);
+    }
+
+    #[test]
+    fn test_is_critical() {
+        let mut game = game();
+        assert!(!is_critical(&game));
+        game.turn = 47;
+        assert!(is_critical(&game));
+        game.turn = 50;
+        assert!(!is_critical(&game));
+
+        // 6 enemy armies three tiles away outnumber our 5, an equal army does not and
+        // larger ones further out do not count.
+        game.raw_map[25 + 7] = 1;
+        game.raw_map[7] = 6;
+        assert!(is_critical(&game));
+        game.raw_map[7] = 5;
+        assert!(!is_critical(&game));
+        game.raw_map[25 + 18] = 1;
+        game.raw_map[18] = 20;
+        assert!(!is_critical(&game));
+    }
+}
@@ -0,0 +1,250 @@
+//! Runs the bot on a thread of its own, so the websocket thread keeps answering pings
+//! and reading updates while the bot thinks.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use game::Game;
+use state::Action;
+use strategy::Strategy;
+
+struct Request {
+    id: u64,
+    game: Game,
+    deadline: u64,
+    notify: Box<dyn FnOnce() + Send>,
+}
+
+/// Everything the bot is told, in the order it happened.
+enum Job {
+    Start(Game),
+    Search(Request),
+    End(bool),
+}
+
+/// The move found for the request with the same id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchResult {
+    pub id: u64,
+    pub action: Action,
+}
+
+pub struct SearchWorker {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<SearchResult>,
+    next_id: u64,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SearchWorker {
+    pub fn spawn(mut strategy: Box<dyn Strategy>) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut queued = None;
+            loop {
+                let job = match queued.take() {
+                    Some(job) => job,
+                    None => {
+                        match job_rx.recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        }
+                    }
+                };
+
+                let request = match job {
+                    Job::Start(game) => {
+                        strategy.game_start(&game);
+                        continue;
+                    }
+                    Job::End(won) => {
+                        strategy.game_end(won);
+                        continue;
+                    }
+                    Job::Search(request) => request,
+                };
+
+                // The bot sees every update, but snapshots queued up while we were busy
+                // are already out of date: only the newest one is worth a move.
+                strategy.observe(&request.game);
+                if let Ok(newer) = job_rx.try_recv() {
+                    queued = Some(newer);
+                    continue;
+                }
+
+                let action = strategy.next_move(&request.game, request.deadline);
+                let result = SearchResult {
+                    id: request.id,
+                    action,
+                };
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+                (request.notify)();
+            }
+        });
+
+        SearchWorker {
+            jobs: Some(job_tx),
+            results: result_rx,
+            next_id: 0,
+            thread: Some(thread),
+        }
+    }
+
+    fn send(&self, job: Job) {
+        self.jobs.as_ref().unwrap().send(job).expect("Search worker thread died");
+    }
+
+    /// Tells the bot a game started, with `game` as set up from `game_start`.
+    pub fn start_game(&mut self, game: Game) {
+        self.send(Job::Start(game));
+    }
+
+    /// Tells the bot the game is over.
+    pub fn end_game(&mut self, won: bool) {
+        self.send(Job::End(won));
+    }
+
+    /// Queues a search of `game` until `deadline` and returns its id. `notify` is called
+    /// from the worker thread once the result is ready; requests overtaken by a newer
+    /// one before they started are dropped without a result.
+    pub fn search<F>(&mut self, game: Game, deadline: u64, notify: F) -> u64
+        where F: FnOnce() + Send + 'static
+    {
+        self.next_id += 1;
+        let request = Request {
+            id: self.next_id,
+            game,
+            deadline,
+            notify: Box::new(notify),
+        };
+        self.send(Job::Search(request));
+        self.next_id
+    }
+
+    /// The most recent result that arrived since the last call, if any.
+    pub fn try_result(&self) -> Option<SearchResult> {
+        self.results.try_iter().last()
+    }
+}
+
+impl Drop for SearchWorker {
+    fn drop(&mut self) {
+        // Closing the request channel ends the thread once its current search is done.
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::StrategyConfig;
+    use game::Game;
+    use state::Action;
+    use std::sync::mpsc::{self, Sender};
+    use std::time::Duration;
+    use strategy::{self, Strategy};
+    use time::precise_time_ns;
+    use worker::SearchWorker;
+
+    /// Reports every hook it sees and always stays put.
+    struct Recorder(Sender<String>);
+
+    impl Strategy for Recorder {
+        fn game_start(&mut self, _game: &Game) {
+            self.0.send("start".to_string()).unwrap();
+        }
+
+        fn observe(&mut self, game: &Game) {
+            self.0.send(format!("observe {}", game.turn)).unwrap();
+        }
+
+        fn next_move(&mut self, game: &Game, _deadline: u64) -> Action {
+            self.0.send(format!("move {}", game.turn)).unwrap();
+            Action::none()
+        }
+
+        fn game_end(&mut self, won: bool) {
+            self.0.send(format!("end {}", won)).unwrap();
+        }
+    }
+
+    fn game() -> Game {
+        Game {
+            initialized: true,
+            player_index: 0,
+            turn: 0,
+
+            width: 3,
+            height: 3,
+
+            cities: vec![],
+            generals: vec![3, 4],
+            scores: vec![10, 2],
+            tiles: vec![1, 2],
+            alive: vec![true, true],
+
+            raw_map: vec![0, 0, 0, 10, 1, 0, 0, 0, 1, -1, -1, -1, 0, 1, -1, -1, -1, 1],
+            ..Game::new()
+        }
+    }
+
+    #[test]
+    fn test_worker() {
+        let mut worker = SearchWorker::spawn(strategy::create(&StrategyConfig::default()));
+        assert_eq!(worker.try_result(), None);
+
+        let (tx, rx) = mpsc::channel();
+        let mut last = 0;
+        for _ in 0..3 {
+            let tx = tx.clone();
+            last = worker.search(game(), precise_time_ns() + 50_000_000, move || {
+                tx.send(()).unwrap();
+            });
+        }
+
+        // Whichever requests were skipped, the last one is always answered.
+        loop {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            if let Some(result) = worker.try_result() {
+                if result.id == last {
+                    assert!(!result.action.is_empty());
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let (tx, rx) = mpsc::channel();
+        {
+            let mut worker = SearchWorker::spawn(Box::new(Recorder(tx)));
+            worker.start_game(Game::new());
+            let mut game = game();
+            for turn in 1..3 {
+                game.turn = turn;
+                worker.search(game.clone(), 0, || ());
+            }
+            let (done_tx, done_rx) = mpsc::channel();
+            game.turn = 3;
+            worker.search(game, 0, move || done_tx.send(()).unwrap());
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            worker.end_game(false);
+        }
+
+        // Skipped updates are still observed, and the last one is always moved on.
+        let log: Vec<String> = rx.iter().collect();
+        assert_eq!(log.first().map(|s| s.as_str()), Some("start"));
+        assert_eq!(log.last().map(|s| s.as_str()), Some("end false"));
+        for turn in 1..4 {
+            assert!(log.contains(&format!("observe {}", turn)));
+        }
+        assert!(log.contains(&"move 3".to_string()));
+    }
+}